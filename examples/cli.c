@@ -12,6 +12,13 @@ int main() {
 
   mpclipboard_config_t *config =
       mpclipboard_config_read(MPCLIPBOARD_CONFIG_READ_OPTION_T_FROM_LOCAL_FILE);
+  if (!config) {
+    char *message = mpclipboard_last_error_message();
+    fprintf(stderr, "failed to read config (code %d): %s\n",
+            mpclipboard_last_error_code(), message);
+    mpclipboard_string_free(message);
+    return 1;
+  }
 
   mpclipboard_handle_t *handle = mpclipboard_thread_start(config);
 
@@ -25,6 +32,17 @@ int main() {
     if (strcmp(line, "exit\n") == 0) {
       break;
     }
+    if (strcmp(line, "history\n") == 0) {
+      mpclipboard_history_t history = mpclipboard_handle_history(handle);
+      for (size_t i = 0; i < history.len; i++) {
+        mpclipboard_history_item_t item = history.items[i];
+        if (item.text) {
+          printf("[%llu] %s\n", (unsigned long long)item.timestamp, item.text);
+        }
+      }
+      mpclipboard_history_free(history);
+      continue;
+    }
     if (mpclipboard_handle_send(handle, line)) {
       fprintf(stderr, "[new] %s\n", line);
     } else {
@@ -33,6 +51,7 @@ int main() {
   }
 
   mpclipboard_handle_stop(handle);
+  free(line);
 
   return 0;
 }
@@ -43,12 +62,34 @@ void *start_polling(void *data) {
     mpclipboard_output_t output = mpclipboard_handle_poll(handle);
     if (output.text) {
       printf("text = %s\n", output.text);
-      free(output.text);
     }
-    if (output.connectivity) {
-      printf("connectivity = %s\n", *output.connectivity ? "true" : "false");
-      free(output.connectivity);
+    if (output.mime) {
+      printf("mime = %s, len = %zu\n", output.mime, output.len);
+    }
+    if (output.has_connectivity) {
+      printf("connectivity = %s\n", output.connectivity ? "true" : "false");
+    }
+    if (output.has_status) {
+      printf("state = %d, retry in %lums\n", output.status.state,
+             (unsigned long)output.status.retry_in_ms);
+    }
+    if (output.auth_error) {
+      printf("auth error = %s\n", output.auth_error);
+    }
+    if (output.has_latency) {
+      printf("latency = %lums\n", (unsigned long)output.latency_ms);
+    }
+    if (output.has_progress) {
+      printf("progress = %zu/%zu\n", output.progress.transferred,
+             output.progress.total);
+    }
+    if (output.decryption_failed) {
+      printf("received clip can't be decrypted\n");
+    }
+    if (output.clip_rejected) {
+      printf("received clip has been rejected\n");
     }
+    mpclipboard_output_free(output);
 
     usleep(100);
   }
@@ -1,7 +1,8 @@
 use anyhow::Result;
 use mpclipboard_generic_client::{
-    ConfigReadOption, Handle, Output, mpclipboard_config_read, mpclipboard_handle_poll,
-    mpclipboard_handle_send, mpclipboard_handle_stop, mpclipboard_init, mpclipboard_thread_start,
+    ConfigReadOption, Handle, History, Output, mpclipboard_config_read, mpclipboard_handle_history,
+    mpclipboard_handle_poll, mpclipboard_handle_send, mpclipboard_handle_stop,
+    mpclipboard_history_free, mpclipboard_init, mpclipboard_output_free, mpclipboard_thread_start,
 };
 use std::io::BufRead as _;
 
@@ -16,18 +17,61 @@ fn main() -> Result<()> {
     std::thread::spawn(move || {
         let handle = sync_handle.unwrap();
         loop {
-            let Output { text, connectivity } = unsafe { mpclipboard_handle_poll(handle) };
+            let output = unsafe { mpclipboard_handle_poll(handle) };
+            let Output {
+                text,
+                mime,
+                len,
+                has_connectivity,
+                connectivity,
+                has_status,
+                status,
+                auth_error,
+                has_latency,
+                latency_ms,
+                has_progress,
+                progress,
+                decryption_failed,
+                clip_rejected,
+                ..
+            } = output;
             if !text.is_null() {
                 log::info!(
                     "text = {:?}",
                     unsafe { std::ffi::CStr::from_ptr(text) }.to_str()
                 );
-                unsafe { free(text.cast()) }
             };
-            if !connectivity.is_null() {
-                log::info!("connectivity = {:?}", unsafe { *connectivity });
-                unsafe { free(connectivity.cast()) }
+            if !mime.is_null() {
+                log::info!(
+                    "mime = {:?}, len = {len}",
+                    unsafe { std::ffi::CStr::from_ptr(mime) }.to_str()
+                );
+            };
+            if has_connectivity {
+                log::info!("connectivity = {connectivity:?}");
+            };
+            if has_status {
+                log::info!("status = {status:?}");
+            };
+            if !auth_error.is_null() {
+                log::info!(
+                    "auth error = {:?}",
+                    unsafe { std::ffi::CStr::from_ptr(auth_error) }.to_str()
+                );
+            };
+            if has_latency {
+                log::info!("latency = {latency_ms}ms");
+            };
+            if has_progress {
+                log::info!("progress = {progress:?}");
+            };
+            if decryption_failed {
+                log::info!("received clip can't be decrypted");
             };
+            if clip_rejected {
+                log::info!("received clip has been rejected");
+            };
+            unsafe { mpclipboard_output_free(output) };
             std::thread::sleep(std::time::Duration::from_millis(100));
         }
     });
@@ -39,6 +83,10 @@ fn main() -> Result<()> {
                 if input == "exit" {
                     break;
                 }
+                if input == "history" {
+                    print_history(handle);
+                    continue;
+                }
                 let input = std::ffi::CString::new(input).unwrap();
                 unsafe { mpclipboard_handle_send(handle, input.as_ptr().cast()) };
             }
@@ -54,6 +102,24 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+fn print_history(handle: *mut Handle) {
+    let history = unsafe { mpclipboard_handle_history(handle) };
+    let History { items, len } = history;
+    if !items.is_null() {
+        for item in unsafe { std::slice::from_raw_parts(items, len) } {
+            if item.text.is_null() {
+                continue;
+            }
+            log::info!(
+                "[{}] {:?}",
+                item.timestamp,
+                unsafe { std::ffi::CStr::from_ptr(item.text) }.to_str()
+            );
+        }
+    }
+    unsafe { mpclipboard_history_free(history) };
+}
+
 struct SyncHandle(usize);
 impl SyncHandle {
     fn new(handle: *mut Handle) -> Self {
@@ -63,7 +129,3 @@ impl SyncHandle {
         self.0 as *mut Handle
     }
 }
-
-unsafe extern "C" {
-    fn free(ptr: *mut std::ffi::c_void);
-}
@@ -1,5 +1,6 @@
 #pragma once
 
+#include <stddef.h>
 #include <stdint.h>
 #include <stdbool.h>
 
@@ -17,6 +18,142 @@ typedef enum {
   MPCLIPBOARD_CONFIG_READ_OPTION_T_FROM_XDG_CONFIG_DIR = 1,
 } mpclipboard_config_read_option_t;
 
+/**
+ * Kind of the last error, see `mpclipboard_last_error_code`
+ */
+typedef enum {
+  /**
+   * The last fallible call on this thread hasn't failed
+   */
+  MPCLIPBOARD_ERROR_CODE_T_NONE = 0,
+  /**
+   * Argument is invalid (e.g. a string is not valid UTF-8 or a list is empty)
+   */
+  MPCLIPBOARD_ERROR_CODE_T_INVALID_ARGUMENT = 1,
+  /**
+   * File doesn't exist (e.g. there's no config file)
+   */
+  MPCLIPBOARD_ERROR_CODE_T_NOT_FOUND = 2,
+  /**
+   * Any other I/O error (e.g. permission denied)
+   */
+  MPCLIPBOARD_ERROR_CODE_T_IO = 3,
+  /**
+   * Config is not a valid TOML or has missing/invalid fields
+   */
+  MPCLIPBOARD_ERROR_CODE_T_INVALID_CONFIG = 4,
+  /**
+   * URI of the server is malformed
+   */
+  MPCLIPBOARD_ERROR_CODE_T_INVALID_URI = 5,
+  /**
+   * TLS connector can't be initialized
+   */
+  MPCLIPBOARD_ERROR_CODE_T_TLS = 6,
+  /**
+   * Background thread is not running (e.g. it has crashed)
+   */
+  MPCLIPBOARD_ERROR_CODE_T_THREAD_NOT_RUNNING = 7,
+  /**
+   * Background thread can't be stopped gracefully
+   */
+  MPCLIPBOARD_ERROR_CODE_T_THREAD_STOP = 8,
+  /**
+   * Clip is larger than `transfer.max_clip_size` of the config
+   */
+  MPCLIPBOARD_ERROR_CODE_T_CLIP_TOO_LARGE = 9,
+} mpclipboard_error_code_t;
+
+/**
+ * Kind of an event, defines which fields of `EventItem` are set
+ */
+typedef enum {
+  /**
+   * Overall status of connections has changed, `status` is set
+   */
+  MPCLIPBOARD_EVENT_KIND_T_STATUS_CHANGED = 0,
+  /**
+   * Server has rejected credentials, `auth_error` is set if the server has sent a reason
+   */
+  MPCLIPBOARD_EVENT_KIND_T_AUTH_FAILED = 1,
+  /**
+   * New clip has been received from the server, `text` or `mime` + `data` + `len` are set
+   */
+  MPCLIPBOARD_EVENT_KIND_T_NEW_CLIP = 2,
+  /**
+   * Progress of a large clip transfer, `progress` is set
+   */
+  MPCLIPBOARD_EVENT_KIND_T_PROGRESS = 3,
+  /**
+   * Round-trip time to the server, `latency_ms` is set
+   */
+  MPCLIPBOARD_EVENT_KIND_T_LATENCY = 4,
+  /**
+   * Local clip has been confirmed by the server, `text` or `mime` + `data` + `len` are set
+   */
+  MPCLIPBOARD_EVENT_KIND_T_CLIP_DELIVERED = 5,
+  /**
+   * Local clip couldn't be delivered, `text` or `mime` + `data` + `len` are set
+   */
+  MPCLIPBOARD_EVENT_KIND_T_CLIP_FAILED = 6,
+  /**
+   * Status of the connection to a single server has changed, `profile` and `status` are set
+   */
+  MPCLIPBOARD_EVENT_KIND_T_PROFILE_STATUS_CHANGED = 7,
+  /**
+   * Received clip couldn't be decrypted (most likely passphrases don't match), no fields are set
+   */
+  MPCLIPBOARD_EVENT_KIND_T_DECRYPTION_FAILED = 8,
+  /**
+   * Received clip has been dropped because it's malformed or too large, no fields are set
+   */
+  MPCLIPBOARD_EVENT_KIND_T_CLIP_REJECTED = 9,
+} mpclipboard_event_kind_t;
+
+/**
+ * State of the connection to the server
+ */
+typedef enum {
+  /**
+   * WebSocket connection is being established
+   */
+  MPCLIPBOARD_CONNECTION_STATE_T_CONNECTING = 0,
+  /**
+   * Credentials are being sent to the server
+   */
+  MPCLIPBOARD_CONNECTION_STATE_T_SENDING_AUTH_REQUEST = 1,
+  /**
+   * Waiting for the server to accept credentials
+   */
+  MPCLIPBOARD_CONNECTION_STATE_T_WAITING_FOR_AUTH_RESPONSE = 2,
+  /**
+   * Connected and authenticated
+   */
+  MPCLIPBOARD_CONNECTION_STATE_T_CONNECTED = 3,
+  /**
+   * Server is unreachable or the connection is lost
+   */
+  MPCLIPBOARD_CONNECTION_STATE_T_DISCONNECTED = 4,
+  /**
+   * Server has rejected credentials (e.g. bad token)
+   */
+  MPCLIPBOARD_CONNECTION_STATE_T_AUTH_FAILED = 5,
+} mpclipboard_connection_state_t;
+
+/**
+ * Direction of a transfer
+ */
+typedef enum {
+  /**
+   * Local clip is being sent to the server
+   */
+  MPCLIPBOARD_DIRECTION_T_UPLOAD = 0,
+  /**
+   * Clip is being received from the server
+   */
+  MPCLIPBOARD_DIRECTION_T_DOWNLOAD = 1,
+} mpclipboard_direction_t;
+
 /**
  * Representation of a runtime configuration
  */
@@ -28,23 +165,245 @@ typedef struct mpclipboard_config_t mpclipboard_config_t;
 typedef struct mpclipboard_handle_t mpclipboard_handle_t;
 
 /**
- * Represents a result of polling
+ * Status of the connection to the server
+ */
+typedef struct {
+  /**
+   * Current state
+   */
+  mpclipboard_connection_state_t state;
+  /**
+   * Time before the next reconnect attempt in milliseconds,
+   * `0` if there's no scheduled attempt
+   */
+  uint64_t retry_in_ms;
+} mpclipboard_connection_status_t;
+
+/**
+ * Progress of a large clip transfer (only large clips are sent in chunks and report progress)
+ */
+typedef struct {
+  /**
+   * Direction of the transfer
+   */
+  mpclipboard_direction_t direction;
+  /**
+   * Number of bytes transferred so far
+   */
+  size_t transferred;
+  /**
+   * Total number of bytes
+   */
+  size_t total;
+} mpclipboard_progress_t;
+
+/**
+ * Single event, fields that don't belong to its `kind` are NULL/zero
  */
 typedef struct {
   /**
-   * Optional (NULLable) text received from the server
+   * Kind of the event
+   */
+  mpclipboard_event_kind_t kind;
+  /**
+   * Text of the clip, set only if the best supported flavor is `text/plain`
    */
   char *text;
   /**
-   * Optional (NULLable) flag of the connectivity state
+   * MIME type of the best supported non-text flavor of the clip
+   */
+  char *mime;
+  /**
+   * Content of the best supported non-text flavor of the clip
+   */
+  uint8_t *data;
+  /**
+   * Length of `data`
+   */
+  size_t len;
+  /**
+   * Status of the connection
+   */
+  mpclipboard_connection_status_t status;
+  /**
+   * Name of the server profile (`"default"` for the main server)
+   */
+  char *profile;
+  /**
+   * Reason of the authentication failure
+   */
+  char *auth_error;
+  /**
+   * Progress of a large clip transfer
+   */
+  mpclipboard_progress_t progress;
+  /**
+   * Round-trip time to the server in milliseconds
+   */
+  uint64_t latency_ms;
+} mpclipboard_event_t;
+
+/**
+ * Owned array of events in the order they have happened.
+ * Must be released with `mpclipboard_event_list_free`.
+ */
+typedef struct {
+  /**
+   * Pointer to the first event, NULL if there are no events
+   */
+  mpclipboard_event_t *items;
+  /**
+   * Number of events
+   */
+  size_t len;
+} mpclipboard_event_list_t;
+
+/**
+ * Callback that is invoked once the server confirms delivery of a clip,
+ * receives `user_data` and `true` if the clip has been delivered (`false` otherwise)
+ */
+typedef void (*mpclipboard_delivery_callback_t)(void *user_data, bool delivered);
+
+/**
+ * Single representation of a clip that is sent to the server
+ */
+typedef struct {
+  /**
+   * MIME type of the content (e.g. `"text/plain"` or `"text/html"`), NULL terminated,
+   * must not be NULL
+   */
+  const char *mime;
+  /**
+   * Raw content (can be NULL if `len` is 0)
    */
-  bool *connectivity;
+  const uint8_t *data;
+  /**
+   * Length of `data`
+   */
+  size_t len;
+} mpclipboard_flavor_input_t;
+
+/**
+ * Represents a result of polling.
+ * Must be released with `mpclipboard_output_free`.
+ */
+typedef struct {
+  /**
+   * Optional (NULLable) text received from the server,
+   * set only if the best supported flavor is `text/plain`
+   */
+  char *text;
+  /**
+   * Optional (NULLable) MIME type of the best supported non-text flavor
+   */
+  char *mime;
+  /**
+   * Optional (NULLable) content of the best supported non-text flavor
+   */
+  uint8_t *data;
+  /**
+   * Length of `data`
+   */
+  size_t len;
+  /**
+   * Set if `connectivity` has changed
+   */
+  bool has_connectivity;
+  /**
+   * Connectivity state
+   */
+  bool connectivity;
+  /**
+   * Set if `status` has changed
+   */
+  bool has_status;
+  /**
+   * Latest status of the connection,
+   * unlike `connectivity` it's also set for intermediate states (e.g. "connecting")
+   */
+  mpclipboard_connection_status_t status;
+  /**
+   * Optional (NULLable) reason of the latest authentication failure
+   * (if the server has sent it), see `mpclipboard_handle_update_credentials`
+   */
+  char *auth_error;
+  /**
+   * Set if `latency_ms` has been measured
+   */
+  bool has_latency;
+  /**
+   * Latest round-trip time to the server in milliseconds
+   */
+  uint64_t latency_ms;
+  /**
+   * Set if `progress` has changed
+   */
+  bool has_progress;
+  /**
+   * Latest progress of a large clip transfer
+   */
+  mpclipboard_progress_t progress;
+  /**
+   * Set if a received clip couldn't be decrypted (most likely passphrases don't match)
+   */
+  bool decryption_failed;
+  /**
+   * Set if a received clip has been dropped because it's malformed or too large
+   */
+  bool clip_rejected;
 } mpclipboard_output_t;
 
+/**
+ * Single entry of the clipboard history
+ */
+typedef struct {
+  /**
+   * Text of the clip, set only if the best supported flavor is `text/plain`
+   */
+  char *text;
+  /**
+   * MIME type of the best supported non-text flavor
+   */
+  char *mime;
+  /**
+   * Content of the best supported non-text flavor
+   */
+  uint8_t *data;
+  /**
+   * Length of `data`
+   */
+  size_t len;
+  /**
+   * Time when the clip was made (in milliseconds since UNIX epoch)
+   */
+  uint64_t timestamp;
+} mpclipboard_history_item_t;
+
+/**
+ * Owned array of history entries, the most recent one goes first.
+ * Clips that have no supported flavors are skipped.
+ * Must be released with `mpclipboard_history_free`.
+ */
+typedef struct {
+  /**
+   * Pointer to the first entry, NULL if history is empty
+   */
+  mpclipboard_history_item_t *items;
+  /**
+   * Number of entries
+   */
+  size_t len;
+} mpclipboard_history_t;
+
+/**
+ * Callback that is invoked every time there's a new event to poll, receives `user_data`
+ */
+typedef void (*mpclipboard_event_callback_t)(void *user_data);
+
 /**
  * Initializes MPClipboard's Logger and TLS connector.
  *
- * This is the first this that you must do before calling any
+ * This is the first thing that you must do before calling any
  * MPClipboard functions.
  *
  * Returns `false` if TLS connector can't be initialized.
@@ -52,20 +411,80 @@ typedef struct {
 bool mpclipboard_init(void);
 
 /**
- * Reads the config based on the given isntruction
+ * Reads the config based on the given instruction
  * (which is either "read from XDG dir" or "read from ./config.toml")
  */
 mpclipboard_config_t *mpclipboard_config_read(mpclipboard_config_read_option_t option);
 
 /**
- * Constrcuts the config in-place based on given parameters that match fields 1-to-1.
+ * Constructs the config in-place based on given parameters that match fields 1-to-1.
  */
 mpclipboard_config_t *mpclipboard_config_new(const char *uri, const char *token, const char *name);
 
+/**
+ * Releases the config that hasn't been passed to
+ * `mpclipboard_thread_start` or `mpclipboard_handle_update_config`
+ * (both take ownership of it).
+ *
+ * # Safety
+ *
+ * `config` must be either NULL or a valid owned pointer to Config
+ */
+void mpclipboard_config_free(mpclipboard_config_t *config);
+
+/**
+ * Sets a passphrase that enables end-to-end encryption of clips.
+ *
+ * # Safety
+ *
+ * `config` must be a valid pointer to Config
+ * `passphrase` must be a NULL terminated C string
+ */
+bool mpclipboard_config_set_passphrase(mpclipboard_config_t *config, const char *passphrase);
+
+/**
+ * Returns the kind of the error reported by the last fallible call on the calling thread.
+ * Every fallible function resets it, so it's `None` if the last call hasn't failed
+ * (e.g. `mpclipboard_handle_send` has returned `false` because the text is a duplicate).
+ */
+mpclipboard_error_code_t mpclipboard_last_error_code(void);
+
+/**
+ * Returns a human-readable description of the error reported by the last
+ * fallible call on the calling thread (including all its causes, e.g.
+ * `"failed to read config.toml: No such file or directory (os error 2)"`)
+ * or NULL if there were no errors.
+ * Returned string must be released with `mpclipboard_string_free`.
+ */
+char *mpclipboard_last_error_message(void);
+
+/**
+ * Releases events returned by `mpclipboard_handle_poll_many`
+ *
+ * # Safety
+ *
+ * `events` must be a value returned by `mpclipboard_handle_poll_many`
+ * that hasn't been released yet
+ */
+void mpclipboard_event_list_free(mpclipboard_event_list_t events);
+
+/**
+ * Releases a string allocated by MPClipboard
+ * (e.g. a field of `mpclipboard_output_t` taken over by the caller)
+ *
+ * # Safety
+ *
+ * `s` must be either NULL or a string allocated by MPClipboard
+ * that hasn't been released yet
+ */
+void mpclipboard_string_free(char *s);
+
 /**
  * Sends text from local clipboard, blocks until background thread receives
  * this text and decides whether it's a duplicate or not. Doesn't wait for delivery.
  * Returns `true` if given text is new (in such case it gets sent to the server).
+ * `false` is returned both for duplicates and failures, the latter can be told apart
+ * by `mpclipboard_last_error_code` returning anything but `None`.
  *
  * # Safety
  *
@@ -74,10 +493,117 @@ mpclipboard_config_t *mpclipboard_config_new(const char *uri, const char *token,
  */
 bool mpclipboard_handle_send(const mpclipboard_handle_t *handle, const char *text);
 
+/**
+ * Sends text from local clipboard, blocks until background thread receives
+ * this text and decides whether it's a duplicate or not.
+ * Returns `true` if given text is new (in such case it gets sent to the server),
+ * then `callback` is invoked exactly once with the delivery status.
+ * `callback` is called on the background thread and must not block.
+ *
+ * # Safety
+ *
+ * `handle` must be a valid pointer to Handle
+ * `text` must be a NULL terminated C string
+ * `user_data` must be safe to use from another thread until `callback` is invoked
+ */
+bool mpclipboard_handle_send_with_callback(const mpclipboard_handle_t *handle,
+                                           const char *text,
+                                           mpclipboard_delivery_callback_t callback,
+                                           void *user_data);
+
+/**
+ * Sends arbitrary content (e.g. `image/png`) from local clipboard, blocks until
+ * background thread receives it and decides whether it's a duplicate or not.
+ * Doesn't wait for delivery.
+ * Returns `true` if given content is new (in such case it gets sent to the server).
+ *
+ * # Safety
+ *
+ * `handle` must be a valid pointer to Handle
+ * `mime` must be a non-NULL, NULL terminated C string (NULL fails with `InvalidArgument`)
+ * `data` must be a valid pointer to `len` bytes (or NULL if `len` is 0)
+ */
+bool mpclipboard_handle_send_bytes(const mpclipboard_handle_t *handle,
+                                   const char *mime,
+                                   const uint8_t *data,
+                                   size_t len);
+
+/**
+ * Sends several representations of the same copy (e.g. `text/plain` + `text/html`)
+ * from local clipboard, blocks until background thread receives it and decides
+ * whether it's a duplicate or not. Doesn't wait for delivery.
+ * Returns `true` if given clip is new (in such case it gets sent to the server).
+ *
+ * # Safety
+ *
+ * `handle` must be a valid pointer to Handle
+ * `flavors` must be a valid pointer to `len` flavors, every `mime` must be non-NULL
+ * (NULL fails with `InvalidArgument`)
+ */
+bool mpclipboard_handle_send_flavors(const mpclipboard_handle_t *handle,
+                                     const mpclipboard_flavor_input_t *flavors,
+                                     size_t len);
+
+/**
+ * Same as `mpclipboard_handle_send_bytes`, but `callback` is invoked exactly once
+ * with the delivery status if the content is new (see `mpclipboard_handle_send_with_callback`).
+ *
+ * # Safety
+ *
+ * `handle` must be a valid pointer to Handle
+ * `mime` must be a non-NULL, NULL terminated C string (NULL fails with `InvalidArgument`)
+ * `data` must be a valid pointer to `len` bytes (or NULL if `len` is 0)
+ * `user_data` must be safe to use from another thread until `callback` is invoked
+ */
+bool mpclipboard_handle_send_bytes_with_callback(const mpclipboard_handle_t *handle,
+                                                 const char *mime,
+                                                 const uint8_t *data,
+                                                 size_t len,
+                                                 mpclipboard_delivery_callback_t callback,
+                                                 void *user_data);
+
+/**
+ * Same as `mpclipboard_handle_send_flavors`, but `callback` is invoked exactly once
+ * with the delivery status if the clip is new (see `mpclipboard_handle_send_with_callback`).
+ *
+ * # Safety
+ *
+ * `handle` must be a valid pointer to Handle
+ * `flavors` must be a valid pointer to `len` flavors, every `mime` must be non-NULL
+ * (NULL fails with `InvalidArgument`)
+ * `user_data` must be safe to use from another thread until `callback` is invoked
+ */
+bool mpclipboard_handle_send_flavors_with_callback(const mpclipboard_handle_t *handle,
+                                                   const mpclipboard_flavor_input_t *flavors,
+                                                   size_t len,
+                                                   mpclipboard_delivery_callback_t callback,
+                                                   void *user_data);
+
+/**
+ * Sets MIME types that the host can put into its clipboard (ordered by preference),
+ * it's used to pick the best flavor of received clips in `mpclipboard_handle_poll`
+ * and `mpclipboard_handle_history`. Empty list (default) means "anything".
+ *
+ * # Safety
+ *
+ * `handle` must be a valid pointer to Handle
+ * `mimes` must be a valid pointer to `len` NULL terminated C strings (or NULL if `len` is 0)
+ */
+bool mpclipboard_handle_set_supported_mimes(mpclipboard_handle_t *handle,
+                                            const char *const *mimes,
+                                            size_t len);
+
 /**
  * Polls background thread for any updates, squashes them and returns back to the caller.
- * Returns a pair of `new text received from the server` + `change of the connectivity`.
- * Both pair items can be empty (e.g. if there were to clips sent from the server)
+ * Returns `new clip received from the server`, `change of the connectivity`,
+ * `latest status of the connection` (with a delay before the next reconnect attempt),
+ * `latest latency`
+ * and `latest progress of a large clip transfer`.
+ * Out of all flavors of the clip the best supported one is returned
+ * (see `mpclipboard_handle_set_supported_mimes`): `text/plain` in `text`,
+ * anything else in `mime` + `data` + `len`.
+ * All items can be empty (e.g. if there were no clips sent from the server).
+ * Returned value must be released with `mpclipboard_output_free`.
  *
  * # Safety
  *
@@ -85,6 +611,118 @@ bool mpclipboard_handle_send(const mpclipboard_handle_t *handle, const char *tex
  */
 mpclipboard_output_t mpclipboard_handle_poll(mpclipboard_handle_t *handle);
 
+/**
+ * Polls background thread for every event that has happened since the last call
+ * in order (unlike `mpclipboard_handle_poll` nothing is squashed).
+ * Clips are represented by their best supported flavor
+ * (see `mpclipboard_handle_set_supported_mimes`), received clips that have
+ * no supported flavors are skipped.
+ * Returned value must be released with `mpclipboard_event_list_free`.
+ *
+ * # Safety
+ *
+ * `handle` must be a valid pointer to Handle
+ */
+mpclipboard_event_list_t mpclipboard_handle_poll_many(mpclipboard_handle_t *handle);
+
+/**
+ * Returns local clipboard history (the most recent clip goes first),
+ * blocks until background thread replies.
+ * Returned value must be released with `mpclipboard_history_free`.
+ *
+ * # Safety
+ *
+ * `handle` must be a valid pointer to Handle
+ */
+mpclipboard_history_t mpclipboard_handle_history(const mpclipboard_handle_t *handle);
+
+/**
+ * Cancels the pending reconnect delay (if any) and connects to the server right away,
+ * useful when the host knows that the network is back (e.g. after waking up from sleep).
+ * Doesn't block.
+ *
+ * # Safety
+ *
+ * `handle` must be a valid pointer to Handle
+ */
+bool mpclipboard_handle_reconnect_now(const mpclipboard_handle_t *handle);
+
+/**
+ * Hints that the network has changed (e.g. Wi-Fi was switched to mobile data),
+ * the current connection (that might be half-dead) is dropped and a new one
+ * is made right away. Doesn't block.
+ *
+ * # Safety
+ *
+ * `handle` must be a valid pointer to Handle
+ */
+bool mpclipboard_handle_notify_network_changed(const mpclipboard_handle_t *handle);
+
+/**
+ * Replaces the token that is used for authentication with the main server
+ * (tokens of additional servers can be changed with `mpclipboard_handle_update_config`).
+ * After an authentication failure the client stops reconnecting until
+ * this function is called, then it connects right away. Doesn't block.
+ *
+ * # Safety
+ *
+ * `handle` must be a valid pointer to Handle
+ * `token` must be a NULL terminated C string
+ */
+bool mpclipboard_handle_update_credentials(const mpclipboard_handle_t *handle, const char *token);
+
+/**
+ * Applies a new config without restarting the background thread,
+ * history and outbound queue are kept. Reconnects only if connection-related
+ * fields (URI, token, name or compression) have changed. Doesn't block.
+ *
+ * # Safety
+ *
+ * `handle` must be a valid pointer to Handle
+ * `config` must be a valid owned pointer to Config
+ */
+bool mpclipboard_handle_update_config(const mpclipboard_handle_t *handle,
+                                      mpclipboard_config_t *config);
+
+/**
+ * Starts watching the config file (see `mpclipboard_config_read_option_t`),
+ * every time it changes the config is reloaded and applied as in
+ * `mpclipboard_handle_update_config`. Invalid configs are logged and ignored.
+ * Doesn't block. Returns `false` if the config file can't be located
+ * (e.g. `$HOME` is not set).
+ *
+ * # Safety
+ *
+ * `handle` must be a valid pointer to Handle
+ */
+bool mpclipboard_handle_watch_config(const mpclipboard_handle_t *handle,
+                                     mpclipboard_config_read_option_t option);
+
+/**
+ * Registers a callback that is invoked every time there's a new event
+ * (e.g. a clip from the server), so the host can `mpclipboard_handle_poll`
+ * without a busy loop. Replaces previously registered callback,
+ * NULL `callback` unregisters it. Doesn't block.
+ *
+ * The callback is invoked on the background thread, one call at a time,
+ * after the event is available to `mpclipboard_handle_poll`. It must return quickly
+ * and must not call functions that wait for the background thread
+ * (`mpclipboard_handle_send*`, `mpclipboard_handle_history` and `mpclipboard_handle_stop`),
+ * these deadlock because the thread is busy until the callback returns.
+ * `mpclipboard_handle_poll`, `mpclipboard_handle_poll_many` and non-blocking functions
+ * are fine, though usually it's better to wake up the host's own event loop
+ * (e.g. `g_idle_add` or `dispatch_async` to the main queue).
+ *
+ * # Safety
+ *
+ * `handle` must be a valid pointer to Handle
+ * `user_data` must be safe to use from another thread until the callback is replaced
+ * or the handle is stopped
+ */
+bool mpclipboard_handle_set_callback(const mpclipboard_handle_t *handle,
+                                     mpclipboard_event_callback_t callback,
+                                     void *user_data);
+
 /**
  * Gracefully shuts down a background thread
  *
@@ -110,10 +748,32 @@ bool mpclipboard_handle_stop(mpclipboard_handle_t *handle);
 int mpclipboard_handle_take_fd(mpclipboard_handle_t *handle);
 
 /**
- * Prints one "info" and onr "error" message, useful for testing
+ * Releases history returned by `mpclipboard_handle_history`
+ *
+ * # Safety
+ *
+ * `history` must be a value returned by `mpclipboard_handle_history`
+ * that hasn't been released yet
+ */
+void mpclipboard_history_free(mpclipboard_history_t history);
+
+/**
+ * Prints one "info" and one "error" message, useful for testing
  */
 void mpclipboard_logger_test(void);
 
+/**
+ * Releases output returned by `mpclipboard_handle_poll`.
+ * Pointer fields that have been released separately
+ * (e.g. via `mpclipboard_string_free`) must be set to NULL beforehand.
+ *
+ * # Safety
+ *
+ * `output` must be a value returned by `mpclipboard_handle_poll`
+ * that hasn't been released yet
+ */
+void mpclipboard_output_free(mpclipboard_output_t output);
+
 /**
  * Starts a background thread with Tokio runtime, returns a "handle" for communication and control.
  *
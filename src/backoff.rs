@@ -0,0 +1,117 @@
+use crate::ReconnectConfig;
+use std::time::Duration;
+use tokio::time::Instant;
+
+pub(crate) struct Backoff {
+    config: ReconnectConfig,
+    attempt: u32,
+    connected_at: Option<Instant>,
+}
+
+impl Backoff {
+    pub(crate) fn new(config: &ReconnectConfig) -> Self {
+        Self {
+            config: config.clone(),
+            attempt: 0,
+            connected_at: None,
+        }
+    }
+
+    pub(crate) fn reconfigure(&mut self, config: &ReconnectConfig) {
+        self.config = config.clone();
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.attempt = 0;
+        self.connected_at = None;
+    }
+
+    pub(crate) fn connected(&mut self) {
+        self.connected_at = Some(Instant::now());
+    }
+
+    // Returns a delay before the next reconnect attempt
+    pub(crate) fn next_delay(&mut self) -> Duration {
+        let reset_after = Duration::from_secs(self.config.reset_after_secs);
+        if let Some(connected_at) = self.connected_at.take()
+            && connected_at.elapsed() >= reset_after
+        {
+            self.attempt = 0;
+        }
+
+        let initial = self.config.initial_delay_ms as f64;
+        let max = self.config.max_delay_ms.max(self.config.initial_delay_ms) as f64;
+        let delay = (initial
+            * self
+                .config
+                .multiplier
+                .max(1.0)
+                .powi(self.attempt.min(64) as i32))
+        .min(max);
+        self.attempt = self.attempt.saturating_add(1);
+
+        let jitter = self.config.jitter.clamp(0.0, 1.0);
+        let delay = delay * (1.0 + jitter * (2.0 * fastrand::f64() - 1.0));
+        Duration::from_millis(delay.clamp(initial, max) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(jitter: f64) -> ReconnectConfig {
+        ReconnectConfig {
+            initial_delay_ms: 100,
+            multiplier: 2.0,
+            max_delay_ms: 1000,
+            jitter,
+            reset_after_secs: 0,
+        }
+    }
+
+    fn delays(backoff: &mut Backoff, count: usize) -> Vec<u64> {
+        (0..count)
+            .map(|_| backoff.next_delay().as_millis() as u64)
+            .collect()
+    }
+
+    #[test]
+    fn delays_grow_exponentially_up_to_max() {
+        let mut backoff = Backoff::new(&config(0.0));
+        assert_eq!(
+            delays(&mut backoff, 7),
+            [100, 200, 400, 800, 1000, 1000, 1000]
+        );
+    }
+
+    #[test]
+    fn jitter_stays_within_bounds() {
+        let mut backoff = Backoff::new(&config(0.5));
+        for _ in 0..100 {
+            backoff.reset();
+            for (delay, expected) in delays(&mut backoff, 6)
+                .into_iter()
+                .zip([100, 200, 400, 800, 1000, 1000])
+            {
+                assert!((100..=1000).contains(&delay), "{delay}");
+                assert!(
+                    delay >= expected / 2 && delay <= expected * 3 / 2,
+                    "{delay}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn delays_start_over_after_reset() {
+        let mut backoff = Backoff::new(&config(0.0));
+        assert_eq!(delays(&mut backoff, 3), [100, 200, 400]);
+        backoff.reset();
+        assert_eq!(delays(&mut backoff, 2), [100, 200]);
+
+        // a connection that lived for at least `reset_after_secs`
+        backoff.connected();
+        assert_eq!(delays(&mut backoff, 2), [100, 200]);
+    }
+}
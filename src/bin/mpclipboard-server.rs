@@ -0,0 +1,18 @@
+use anyhow::Result;
+use mpclipboard_generic_client::{Logger, Relay, RelayConfig};
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    Logger::init();
+
+    let path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| String::from("server.toml"));
+    let config = RelayConfig::read(&path)?;
+    log::info!("{config:?}");
+
+    let relay = Relay::start(config).await?;
+    tokio::signal::ctrl_c().await?;
+    relay.stop().await;
+    Ok(())
+}
@@ -0,0 +1,153 @@
+use crate::progress::{Direction, Progress};
+use anyhow::{Result, bail};
+use std::collections::HashMap;
+
+// Chunk layout:
+// [MAGIC][u64 BE: transfer id (id of the clip)][u32 BE: index][u32 BE: count][u64 BE: total length][payload]
+const MAGIC: &[u8] = b"MPCK";
+const HEADER_LEN: usize = MAGIC.len() + 8 + 4 + 4 + 8;
+
+// transfers of disconnected senders are never finished,
+// so there's a limit on how many of them can be tracked at once
+const MAX_TRANSFERS: usize = 8;
+
+pub(crate) fn is_chunk(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+pub(crate) struct Header {
+    pub(crate) id: u64,
+    pub(crate) index: u32,
+    pub(crate) count: u32,
+    pub(crate) total: usize,
+}
+
+// Returns the header and the payload of the chunk
+pub(crate) fn parse(bytes: &[u8]) -> Option<(Header, &[u8])> {
+    if bytes.len() < HEADER_LEN || !is_chunk(bytes) {
+        return None;
+    }
+    let (header, payload) = bytes[MAGIC.len()..].split_at(HEADER_LEN - MAGIC.len());
+    let header = Header {
+        id: u64::from_be_bytes(header[0..8].try_into().expect("8 bytes")),
+        index: u32::from_be_bytes(header[8..12].try_into().expect("4 bytes")),
+        count: u32::from_be_bytes(header[12..16].try_into().expect("4 bytes")),
+        total: u64::from_be_bytes(header[16..24].try_into().expect("8 bytes")) as usize,
+    };
+    Some((header, payload))
+}
+
+pub(crate) fn split(payload: &[u8], chunk_size: usize, id: u64) -> Vec<(Vec<u8>, Progress)> {
+    let chunk_size = chunk_size.max(1);
+    let count = payload.len().div_ceil(chunk_size);
+
+    let mut transferred = 0;
+    payload
+        .chunks(chunk_size)
+        .enumerate()
+        .map(|(index, part)| {
+            let mut chunk = Vec::with_capacity(HEADER_LEN + part.len());
+            chunk.extend_from_slice(MAGIC);
+            chunk.extend_from_slice(&id.to_be_bytes());
+            chunk.extend_from_slice(&(index as u32).to_be_bytes());
+            chunk.extend_from_slice(&(count as u32).to_be_bytes());
+            chunk.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+            chunk.extend_from_slice(part);
+
+            transferred += part.len();
+            let progress = Progress {
+                direction: Direction::Upload,
+                transferred,
+                total: payload.len(),
+            };
+            (chunk, progress)
+        })
+        .collect()
+}
+
+pub(crate) enum Reassembled {
+    InProgress(Progress),
+    Done(Vec<u8>),
+}
+
+struct Transfer {
+    next_index: u32,
+    count: u32,
+    total: usize,
+    data: Vec<u8>,
+}
+
+#[derive(Default)]
+pub(crate) struct Reassembler {
+    transfers: HashMap<u64, Transfer>,
+}
+
+impl Reassembler {
+    pub(crate) fn clear(&mut self) {
+        self.transfers.clear();
+    }
+
+    pub(crate) fn push(&mut self, bytes: &[u8], max_size: usize) -> Result<Reassembled> {
+        let Some((
+            Header {
+                id,
+                index,
+                count,
+                total,
+            },
+            payload,
+        )) = parse(bytes)
+        else {
+            bail!("malformed chunk");
+        };
+
+        if index == 0 {
+            if total > max_size {
+                bail!("incoming clip is too large ({total} bytes, max is {max_size})");
+            }
+            if self.transfers.len() >= MAX_TRANSFERS {
+                log::warn!("[chunks] too many unfinished transfers, dropping them");
+                self.transfers.clear();
+            }
+            self.transfers.insert(
+                id,
+                Transfer {
+                    next_index: 0,
+                    count,
+                    total,
+                    // grows as chunks arrive, `total` is only what the peer claims
+                    data: Vec::new(),
+                },
+            );
+        }
+
+        let Some(transfer) = self.transfers.get_mut(&id) else {
+            bail!("chunk {index} of unknown transfer {id}");
+        };
+        if transfer.next_index != index || transfer.count != count || transfer.total != total {
+            self.transfers.remove(&id);
+            bail!("chunk {index} of transfer {id} is out of order");
+        }
+        if transfer.data.len() + payload.len() > transfer.total {
+            self.transfers.remove(&id);
+            bail!("transfer {id} exceeds its declared length");
+        }
+
+        transfer.data.extend_from_slice(payload);
+        transfer.next_index += 1;
+
+        if transfer.next_index < transfer.count {
+            return Ok(Reassembled::InProgress(Progress {
+                direction: Direction::Download,
+                transferred: transfer.data.len(),
+                total: transfer.total,
+            }));
+        }
+
+        let transfer = self.transfers.remove(&id).expect("exists");
+        if transfer.data.len() != transfer.total {
+            bail!("transfer {id} is incomplete");
+        }
+        Ok(Reassembled::Done(transfer.data))
+    }
+}
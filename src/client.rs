@@ -0,0 +1,52 @@
+use crate::{Config, Handle, handle::Worker, main_loop::MainLoop};
+use anyhow::{Context as _, Result};
+use std::sync::{Arc, atomic::AtomicUsize};
+use tokio::sync::mpsc::unbounded_channel;
+use tokio_util::sync::CancellationToken;
+
+/// Entry point to run MPClipboard inside of an existing Tokio runtime
+/// (see `Thread` for a dedicated background thread)
+pub struct Client;
+
+impl Client {
+    /// Creates a "handle" for communication and control and a future that runs
+    /// MPClipboard until the handle is stopped. The future can be either awaited
+    /// directly or spawned on any Tokio runtime (with I/O and time drivers enabled).
+    pub fn run(config: Config) -> Result<(Handle, impl Future<Output = ()> + Send + 'static)> {
+        let (ctx, crx) = unbounded_channel();
+        let (etx, erx) = unbounded_channel();
+        let token = CancellationToken::new();
+        let (pipe_reader, pipe_writer) = std::io::pipe().context("failed to create io pipe")?;
+        let max_clip_size = Arc::new(AtomicUsize::new(config.transfer.max_clip_size));
+
+        let run = {
+            let token = token.clone();
+            let max_clip_size = Arc::clone(&max_clip_size);
+            async move {
+                MainLoop::new(crx, etx, config, token, pipe_writer, max_clip_size)
+                    .start()
+                    .await;
+            }
+        };
+
+        let handle = Handle {
+            ctx,
+            erx: Some(erx),
+            token,
+            worker: Worker::External,
+            pipe_reader: Some(pipe_reader),
+            supported: vec![],
+            max_clip_size,
+        };
+
+        Ok((handle, run))
+    }
+
+    /// Spawns MPClipboard as a task on the given Tokio runtime,
+    /// returns a "handle" for communication and control.
+    pub fn spawn_on(rt: &tokio::runtime::Handle, config: Config) -> Result<Handle> {
+        let (mut handle, run) = Self::run(config)?;
+        handle.worker = Worker::Task(rt.spawn(run));
+        Ok(handle)
+    }
+}
@@ -1,16 +1,75 @@
+use anyhow::{Context as _, Result, bail};
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
 use serde::{Deserialize, Serialize};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-pub(crate) struct Clip {
-    pub(crate) text: String,
-    pub(crate) timestamp: u128,
+/// MIME type of plain text clips
+pub const TEXT_PLAIN: &str = "text/plain";
+
+/// Single representation of a clip (e.g. `text/plain` or `text/html` version of the same copy)
+#[derive(Clone, PartialEq, Eq)]
+pub struct Flavor {
+    /// MIME type of the content (e.g. `"text/plain"` or `"image/png"`)
+    pub mime: String,
+    /// Raw content
+    pub data: Vec<u8>,
+}
+
+impl Flavor {
+    /// Creates a flavor from given MIME type and content
+    pub fn new(mime: &str, data: &[u8]) -> Self {
+        Self {
+            mime: mime.into(),
+            data: data.into(),
+        }
+    }
+
+    /// Returns content as a string if it's a `text/plain` flavor
+    pub fn as_text(&self) -> Option<&str> {
+        if self.mime != TEXT_PLAIN {
+            return None;
+        }
+        std::str::from_utf8(&self.data).ok()
+    }
+}
+
+impl std::fmt::Debug for Flavor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut out = f.debug_struct("Flavor");
+        out.field("mime", &self.mime);
+        match self.as_text() {
+            Some(text) => out.field("text", &text),
+            None => out.field("len", &self.data.len()),
+        };
+        out.finish()
+    }
+}
+
+/// Single clipboard entry, a logical copy that can have several representations
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(try_from = "ClipRepr", into = "ClipRepr")]
+pub struct Clip {
+    /// Alternative representations of the same copy, the preferred one goes first
+    pub flavors: Vec<Flavor>,
+    /// Time when the clip was made (in milliseconds since UNIX epoch)
+    pub timestamp: u128,
 }
 
 impl Clip {
-    pub(crate) fn new(text: &str) -> Self {
+    /// Creates a `text/plain` clip made right now
+    pub fn new(text: &str) -> Self {
+        Self::bytes(TEXT_PLAIN, text.as_bytes())
+    }
+
+    /// Creates a clip with arbitrary content made right now
+    pub fn bytes(mime: &str, data: &[u8]) -> Self {
+        Self::with_flavors(vec![Flavor::new(mime, data)])
+    }
+
+    /// Creates a clip with several representations made right now
+    pub fn with_flavors(flavors: Vec<Flavor>) -> Self {
         Self {
-            text: text.into(),
+            flavors,
             timestamp: SystemTime::now()
                 .duration_since(UNIX_EPOCH)
                 .expect("Time went backwards")
@@ -18,7 +77,196 @@ impl Clip {
         }
     }
 
+    /// Returns a flavor with given MIME type
+    pub fn flavor(&self, mime: &str) -> Option<&Flavor> {
+        self.flavors.iter().find(|flavor| flavor.mime == mime)
+    }
+
+    /// Returns `text/plain` representation of the clip (if any)
+    pub fn as_text(&self) -> Option<&str> {
+        self.flavor(TEXT_PLAIN).and_then(Flavor::as_text)
+    }
+
+    /// Picks the best flavor based on the list of supported MIME types
+    /// (ordered by preference). Empty list means "anything", in such case
+    /// the preferred flavor of the sender is returned.
+    pub fn best_flavor(&self, supported: &[impl AsRef<str>]) -> Option<&Flavor> {
+        if supported.is_empty() {
+            return self.flavors.first();
+        }
+        supported.iter().find_map(|mime| self.flavor(mime.as_ref()))
+    }
+
+    // Clips are compared by their plain text representation if both have it,
+    // this way a rich copy and its plain text echo from another client
+    // are treated as the same logical clip.
+    pub(crate) fn same_content(&self, other: &Clip) -> bool {
+        match (self.flavor(TEXT_PLAIN), other.flavor(TEXT_PLAIN)) {
+            (Some(lhs), Some(rhs)) => lhs.data == rhs.data,
+            _ => {
+                self.flavors.len() == other.flavors.len()
+                    && self
+                        .flavors
+                        .iter()
+                        .all(|flavor| other.flavor(&flavor.mime) == Some(flavor))
+            }
+        }
+    }
+
     pub(crate) fn newer_than(&self, other: &Clip) -> bool {
-        self.timestamp > other.timestamp && self.text != other.text
+        self.timestamp > other.timestamp && !self.same_content(other)
+    }
+
+    pub(crate) fn size(&self) -> usize {
+        self.flavors.iter().map(|flavor| flavor.data.len()).sum()
+    }
+
+    pub(crate) fn is_plain_text(&self) -> bool {
+        matches!(self.flavors.as_slice(), [flavor] if flavor.as_text().is_some())
+    }
+
+    // Binary frame layout:
+    // [u32 BE: header length][JSON header][content of all flavors, one after another]
+    pub(crate) fn to_binary(&self) -> Vec<u8> {
+        let header = serde_json::to_vec(&BinaryHeader {
+            flavors: self
+                .flavors
+                .iter()
+                .map(|flavor| BinaryFlavor {
+                    mime: flavor.mime.clone(),
+                    len: flavor.data.len(),
+                })
+                .collect(),
+            timestamp: self.timestamp,
+        })
+        .expect("failed to serialize clip header");
+
+        let mut out = Vec::with_capacity(4 + header.len() + self.size());
+        out.extend_from_slice(&(header.len() as u32).to_be_bytes());
+        out.extend_from_slice(&header);
+        for flavor in &self.flavors {
+            out.extend_from_slice(&flavor.data);
+        }
+        out
+    }
+
+    pub(crate) fn from_binary(bytes: &[u8]) -> Result<Self> {
+        let Some((len, rest)) = bytes.split_first_chunk::<4>() else {
+            bail!("binary clip is too short");
+        };
+        let len = u32::from_be_bytes(*len) as usize;
+        if rest.len() < len {
+            bail!("binary clip header is truncated");
+        }
+        let (header, mut data) = rest.split_at(len);
+        let header = serde_json::from_slice::<BinaryHeader>(header)
+            .context("malformed binary clip header")?;
+
+        let mut flavors = Vec::with_capacity(header.flavors.len());
+        for BinaryFlavor { mime, len } in header.flavors {
+            if data.len() < len {
+                bail!("content of {mime} is truncated");
+            }
+            let (content, rest) = data.split_at(len);
+            flavors.push(Flavor {
+                mime,
+                data: content.to_vec(),
+            });
+            data = rest;
+        }
+        if !data.is_empty() {
+            bail!("binary clip has {} trailing byte(s)", data.len());
+        }
+        if flavors.is_empty() {
+            bail!("binary clip has no flavors");
+        }
+
+        Ok(Self {
+            flavors,
+            timestamp: header.timestamp,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct BinaryHeader {
+    flavors: Vec<BinaryFlavor>,
+    timestamp: u128,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BinaryFlavor {
+    mime: String,
+    len: usize,
+}
+
+// Plain text clips keep the original `{ "text": ..., "timestamp": ... }` shape,
+// everything else carries a list of flavors with base64-encoded content.
+#[derive(Serialize, Deserialize)]
+struct ClipRepr {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    flavors: Option<Vec<FlavorRepr>>,
+    timestamp: u128,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FlavorRepr {
+    mime: String,
+    data: String,
+}
+
+impl From<Clip> for ClipRepr {
+    fn from(clip: Clip) -> Self {
+        if clip.is_plain_text() {
+            return Self {
+                text: clip.as_text().map(str::to_string),
+                flavors: None,
+                timestamp: clip.timestamp,
+            };
+        }
+
+        Self {
+            text: None,
+            flavors: Some(
+                clip.flavors
+                    .into_iter()
+                    .map(|flavor| FlavorRepr {
+                        mime: flavor.mime,
+                        data: BASE64.encode(&flavor.data),
+                    })
+                    .collect(),
+            ),
+            timestamp: clip.timestamp,
+        }
+    }
+}
+
+impl TryFrom<ClipRepr> for Clip {
+    type Error = anyhow::Error;
+
+    fn try_from(repr: ClipRepr) -> Result<Self> {
+        let flavors = match (repr.text, repr.flavors) {
+            (Some(text), None) => vec![Flavor {
+                mime: TEXT_PLAIN.to_string(),
+                data: text.into_bytes(),
+            }],
+            (None, Some(flavors)) if !flavors.is_empty() => flavors
+                .into_iter()
+                .map(|FlavorRepr { mime, data }| {
+                    let data = BASE64
+                        .decode(data)
+                        .with_context(|| format!("malformed base64 content of {mime}"))?;
+                    Ok(Flavor { mime, data })
+                })
+                .collect::<Result<Vec<_>>>()?,
+            _ => bail!("clip must have either `text` or non-empty `flavors`"),
+        };
+
+        Ok(Self {
+            flavors,
+            timestamp: repr.timestamp,
+        })
     }
 }
@@ -0,0 +1,34 @@
+use crate::{Config, clip::Clip};
+use anyhow::Result;
+use tokio::sync::oneshot::Sender;
+
+// Invoked on the background thread once the server confirms delivery of a clip (or refuses it)
+pub(crate) type OnDelivered = Box<dyn FnOnce(Result<()>) + Send>;
+
+// Invoked on the background thread every time there's a new event to poll
+pub(crate) type Callback = Box<dyn Fn() + Send>;
+
+pub(crate) enum Command {
+    Send {
+        clip: Clip,
+        reply: Sender<bool>,
+        on_delivered: Option<OnDelivered>,
+    },
+    History {
+        reply: Sender<Vec<Clip>>,
+    },
+    ReconnectNow,
+    NetworkChanged,
+    UpdateCredentials {
+        token: String,
+    },
+    UpdateConfig {
+        config: Box<Config>,
+    },
+    WatchConfig {
+        path: String,
+    },
+    SetCallback {
+        callback: Option<Callback>,
+    },
+}
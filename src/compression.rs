@@ -0,0 +1,51 @@
+use anyhow::{Context as _, Result, bail};
+use flate2::{Compression as Level, read::DeflateDecoder, write::DeflateEncoder};
+use serde::{Deserialize, Serialize};
+use std::io::{Read as _, Write as _};
+
+// Envelope layout:
+// [MAGIC][deflate stream]
+const MAGIC: &[u8] = b"MPCZ";
+
+/// Compression algorithms, negotiated with the server during authentication
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Compression {
+    Deflate,
+}
+
+impl Compression {
+    pub(crate) const SUPPORTED: &[Compression] = &[Compression::Deflate];
+
+    pub(crate) fn compress(self, payload: &[u8]) -> Vec<u8> {
+        match self {
+            Compression::Deflate => {
+                let mut encoder = DeflateEncoder::new(MAGIC.to_vec(), Level::default());
+                encoder
+                    .write_all(payload)
+                    .expect("writing to Vec never fails");
+                encoder.finish().expect("writing to Vec never fails")
+            }
+        }
+    }
+}
+
+pub(crate) fn is_compressed(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+pub(crate) fn decompress(bytes: &[u8], max_size: usize) -> Result<Vec<u8>> {
+    let Some(stream) = bytes.strip_prefix(MAGIC) else {
+        bail!("not a compressed clip");
+    };
+
+    let mut out = vec![];
+    DeflateDecoder::new(stream)
+        .take(max_size as u64 + 1)
+        .read_to_end(&mut out)
+        .context("malformed deflate stream")?;
+    if out.len() > max_size {
+        bail!("decompressed clip is too large (max is {max_size} bytes)");
+    }
+    Ok(out)
+}
@@ -1,9 +1,12 @@
-use anyhow::{Context as _, Result};
+use anyhow::{Context as _, Result, bail};
 use http::Uri;
 use serde::{Deserialize, Serialize};
-use std::{ffi::c_char, str::FromStr};
+use std::{ffi::c_char, net::SocketAddr, path::PathBuf, str::FromStr};
 
-use crate::ffi::cstring_to_string;
+use crate::{
+    error::{ErrorCode, clear_last_error, set_last_error},
+    ffi::cstring_to_string,
+};
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
@@ -17,12 +20,12 @@ pub enum ConfigReadOption {
 }
 
 impl ConfigReadOption {
-    fn path(self) -> String {
+    pub(crate) fn path(self) -> Result<String> {
         match self {
-            ConfigReadOption::FromLocalFile => "config.toml".to_string(),
+            ConfigReadOption::FromLocalFile => Ok("config.toml".to_string()),
             ConfigReadOption::FromXdgConfigDir => {
-                let home = std::env::var("HOME").expect("no $HOME");
-                format!("{home}/.config/mpclipboard/config.toml")
+                let home = std::env::var("HOME").context("$HOME is not set")?;
+                Ok(format!("{home}/.config/mpclipboard/config.toml"))
             }
         }
     }
@@ -42,6 +45,302 @@ pub struct Config {
     /// Unique name of the client
     /// (e.g. `"macos-old-laptop"` or `"linux-dusty-minipc"`)
     pub name: String,
+
+    /// Optional passphrase that enables end-to-end encryption,
+    /// must be the same on all clients.
+    /// Server only relays encrypted clips and can't read them.
+    #[serde(default)]
+    pub passphrase: Option<String>,
+
+    /// Local clipboard history settings
+    #[serde(default)]
+    pub history: HistoryConfig,
+
+    /// Settings of large clips transfer
+    #[serde(default)]
+    pub transfer: TransferConfig,
+
+    /// Settings of the queue of clips that are waiting to be sent
+    #[serde(default)]
+    pub queue: QueueConfig,
+
+    /// Settings of reconnecting to the server
+    #[serde(default)]
+    pub reconnect: ReconnectConfig,
+
+    /// Settings of keeping the connection alive
+    #[serde(default)]
+    pub heartbeat: HeartbeatConfig,
+
+    /// Additional servers that clips are synced with at the same time,
+    /// the main one (`uri` + `token`) is called `"default"`
+    #[serde(default)]
+    pub servers: Vec<ServerConfig>,
+
+    /// Which servers local clips are sent to
+    #[serde(default)]
+    pub routing: RoutingPolicy,
+
+    /// Settings of syncing with other clients in the local network (without a server)
+    #[serde(default)]
+    pub lan: LanConfig,
+}
+
+/// Settings of syncing with other clients in the local network.
+/// Clients find each other with UDP beacons and send clips directly,
+/// beacons are signed and clips are encrypted with `passphrase` (that is required),
+/// so only clients that know it can exchange clips.
+/// Clocks of all clients must be in sync (within 5 minutes).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct LanConfig {
+    /// Whether LAN sync is enabled
+    pub enabled: bool,
+
+    /// UDP port of beacons, must be the same on all clients that rely on broadcasts
+    pub port: u16,
+
+    /// How often beacons are sent, in seconds.
+    /// Clients that haven't sent a beacon for 3 intervals are forgotten
+    pub beacon_interval_secs: u64,
+
+    /// Whether beacons are broadcast to the whole network (`255.255.255.255`)
+    pub broadcast: bool,
+
+    /// Addresses that beacons are sent to directly (e.g. `"192.168.1.20:48211"`),
+    /// useful in networks that block broadcasts
+    pub peers: Vec<SocketAddr>,
+}
+
+impl Default for LanConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: 48211,
+            beacon_interval_secs: 5,
+            broadcast: true,
+            peers: vec![],
+        }
+    }
+}
+
+/// Name of the server profile made of the top-level `uri` and `token`
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Additional server that clips are synced with
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ServerConfig {
+    /// Unique name of the profile (e.g. `"work"`), used in per-profile events
+    /// and in the name of the profile's queue file, so only `[A-Za-z0-9_-]` is allowed
+    pub profile: String,
+
+    /// URI of the WebSocket server
+    #[serde(with = "http_serde::uri")]
+    pub uri: Uri,
+
+    /// Token that is used for authentication
+    pub token: String,
+
+    /// Passphrase of end-to-end encryption, the top-level one is used if it's not set
+    #[serde(default)]
+    pub passphrase: Option<String>,
+
+    /// Whether local clips can be sent to this server,
+    /// clips from the server are received either way
+    #[serde(default = "default_send")]
+    pub send: bool,
+}
+
+fn default_send() -> bool {
+    true
+}
+
+/// Which servers local clips are sent to
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RoutingPolicy {
+    /// Every server that accepts local clips (see `ServerConfig::send`)
+    #[default]
+    All,
+    /// The first connected server that accepts local clips (in order of the config,
+    /// the default one goes first), if none is connected the clip is queued for the first one
+    Failover,
+}
+
+// Connection to a single server, built from the top-level config
+pub(crate) struct Profile {
+    pub(crate) name: String,
+    pub(crate) config: Config,
+    pub(crate) send: bool,
+}
+
+/// Settings of the local clipboard history
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct HistoryConfig {
+    /// Maximum number of clips that are kept in history
+    pub size: usize,
+
+    /// Whether history should be saved on disk and restored on startup
+    pub persist: bool,
+
+    /// Path of the history file, defaults to
+    /// `$XDG_DATA_HOME/mpclipboard/history.jsonl`
+    /// (i.e. `~/.local/share/mpclipboard/history.jsonl`)
+    pub path: Option<PathBuf>,
+
+    /// Clips older than this number of seconds are dropped from history
+    pub max_age_secs: Option<u64>,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            size: 50,
+            persist: false,
+            path: None,
+            max_age_secs: None,
+        }
+    }
+}
+
+/// Settings of large clips transfer
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct TransferConfig {
+    /// Clips larger than this number of bytes are sent in chunks
+    pub chunk_size: usize,
+
+    /// Maximum size of a clip in bytes, larger clips are rejected
+    /// both when sent locally and when received from the server
+    pub max_clip_size: usize,
+
+    /// Whether clips can be compressed (if server supports it)
+    pub compress: bool,
+
+    /// Clips smaller than this number of bytes are never compressed
+    pub compression_threshold: usize,
+}
+
+impl Default for TransferConfig {
+    fn default() -> Self {
+        Self {
+            chunk_size: 64 * 1024,
+            max_clip_size: 16 * 1024 * 1024,
+            compress: true,
+            compression_threshold: 4 * 1024,
+        }
+    }
+}
+
+/// What to keep in the outbound queue when clips can't be sent right away
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum QueuePolicy {
+    /// Keep every clip
+    KeepAll,
+    /// Keep last `QueueConfig::size` clips
+    KeepLastN,
+    /// Keep only the most recent clip
+    KeepLatestOnly,
+}
+
+/// Settings of the queue of clips that are waiting to be sent
+/// (e.g. while there's no connection to the server)
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct QueueConfig {
+    /// What to keep in the queue
+    pub policy: QueuePolicy,
+
+    /// Maximum number of clips for `QueuePolicy::KeepLastN`
+    pub size: usize,
+
+    /// Whether the queue should be saved on disk and restored on startup
+    pub persist: bool,
+
+    /// Path of the queue file, defaults to
+    /// `$XDG_DATA_HOME/mpclipboard/queue.jsonl`
+    /// (i.e. `~/.local/share/mpclipboard/queue.jsonl`)
+    pub path: Option<PathBuf>,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            policy: QueuePolicy::KeepLastN,
+            size: 10,
+            persist: false,
+            path: None,
+        }
+    }
+}
+
+/// Settings of reconnecting to the server, delays grow exponentially
+/// from `initial_delay_ms` up to `max_delay_ms`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ReconnectConfig {
+    /// Delay before the first reconnect attempt in milliseconds
+    pub initial_delay_ms: u64,
+
+    /// Every next attempt waits this many times longer than the previous one
+    pub multiplier: f64,
+
+    /// Maximum delay between attempts in milliseconds
+    pub max_delay_ms: u64,
+
+    /// Random deviation of every delay (`0.2` means +-20%),
+    /// so clients don't reconnect in lockstep after a server outage.
+    /// Delays never go below `initial_delay_ms` or above `max_delay_ms`
+    pub jitter: f64,
+
+    /// Once a connection stays alive for this number of seconds
+    /// delays start from `initial_delay_ms` again
+    pub reset_after_secs: u64,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            initial_delay_ms: 1000,
+            multiplier: 2.0,
+            max_delay_ms: 60_000,
+            jitter: 0.2,
+            reset_after_secs: 30,
+        }
+    }
+}
+
+/// Settings of keeping the connection alive
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct HeartbeatConfig {
+    /// How often the background thread checks timeouts and sends pings, in milliseconds
+    pub tick_interval_ms: u64,
+
+    /// How often the client pings the server (to measure latency), in seconds,
+    /// `0` disables client pings
+    pub ping_interval_secs: u64,
+
+    /// Connection is considered dead if nothing (neither PING nor PONG)
+    /// comes from the server for this number of seconds
+    pub liveness_timeout_secs: u64,
+
+    /// Connection attempt (including authentication) is abandoned
+    /// if it takes longer than this number of seconds
+    pub connect_timeout_secs: u64,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            tick_interval_ms: 1000,
+            ping_interval_secs: 5,
+            liveness_timeout_secs: 15,
+            connect_timeout_secs: 10,
+        }
+    }
 }
 
 impl std::fmt::Debug for Config {
@@ -50,6 +349,27 @@ impl std::fmt::Debug for Config {
             .field("uri", &self.uri)
             .field("token", &"******")
             .field("name", &self.name)
+            .field("passphrase", &self.passphrase.as_ref().map(|_| "******"))
+            .field("history", &self.history)
+            .field("transfer", &self.transfer)
+            .field("queue", &self.queue)
+            .field("reconnect", &self.reconnect)
+            .field("heartbeat", &self.heartbeat)
+            .field("servers", &self.servers)
+            .field("routing", &self.routing)
+            .field("lan", &self.lan)
+            .finish()
+    }
+}
+
+impl std::fmt::Debug for ServerConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ServerConfig")
+            .field("profile", &self.profile)
+            .field("uri", &self.uri)
+            .field("token", &"******")
+            .field("passphrase", &self.passphrase.as_ref().map(|_| "******"))
+            .field("send", &self.send)
             .finish()
     }
 }
@@ -58,21 +378,99 @@ impl Config {
     /// Reads the config based on the given instruction
     /// (which is either "read from XDG dir" or "read from ./config.toml")
     pub fn read(option: ConfigReadOption) -> Result<Self> {
-        let path = option.path();
+        Self::read_path(&option.path()?)
+    }
+
+    pub(crate) fn read_path(path: &str) -> Result<Self> {
         let content =
-            std::fs::read_to_string(&path).with_context(|| format!("failed to read {path}"))?;
-        toml::from_str(&content).context("invalid config format")
+            std::fs::read_to_string(path).with_context(|| format!("failed to read {path}"))?;
+        let config: Self = toml::from_str(&content).context("invalid config format")?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        let mut seen = vec![DEFAULT_PROFILE];
+        for server in &self.servers {
+            if !is_valid_profile_name(&server.profile) {
+                bail!(
+                    "invalid server profile {:?}, only A-Z, a-z, 0-9, '_' and '-' are allowed",
+                    server.profile
+                );
+            }
+            if seen.contains(&server.profile.as_str()) {
+                bail!("duplicate server profile {:?}", server.profile);
+            }
+            seen.push(&server.profile);
+        }
+        Ok(())
+    }
+
+    // Splits the config into one config per server, the default one goes first.
+    // Profiles with invalid or duplicate names are skipped (`Config::read` rejects them,
+    // but the config can also be built manually).
+    pub(crate) fn profiles(&self) -> Vec<Profile> {
+        let base = Config {
+            servers: vec![],
+            ..self.clone()
+        };
+        let mut profiles = vec![Profile {
+            name: DEFAULT_PROFILE.to_string(),
+            config: base.clone(),
+            send: true,
+        }];
+        for server in &self.servers {
+            if !is_valid_profile_name(&server.profile) {
+                log::warn!("skipping invalid server profile {:?}", server.profile);
+                continue;
+            }
+            if profiles
+                .iter()
+                .any(|profile| profile.name == server.profile)
+            {
+                log::warn!("skipping duplicate server profile {:?}", server.profile);
+                continue;
+            }
+            profiles.push(Profile {
+                name: server.profile.clone(),
+                config: Config {
+                    uri: server.uri.clone(),
+                    token: server.token.clone(),
+                    passphrase: server.passphrase.clone().or(base.passphrase.clone()),
+                    ..base.clone()
+                },
+                send: server.send,
+            });
+        }
+        profiles
+    }
+
+    // Fields that can't be changed without reconnecting
+    pub(crate) fn same_connection(&self, other: &Config) -> bool {
+        self.uri == other.uri
+            && self.token == other.token
+            && self.name == other.name
+            && self.transfer.compress == other.transfer.compress
     }
 }
 
+// Profile names become a part of file names, so they must not contain path separators
+fn is_valid_profile_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'-')
+}
+
 #[unsafe(no_mangle)]
 /// Reads the config based on the given instruction
 /// (which is either "read from XDG dir" or "read from ./config.toml")
 pub extern "C" fn mpclipboard_config_read(option: ConfigReadOption) -> *mut Config {
+    clear_last_error();
     let config = match Config::read(option) {
         Ok(config) => config,
         Err(err) => {
-            log::error!("{err:?}");
+            set_last_error(ErrorCode::InvalidConfig, err);
             return std::ptr::null_mut();
         }
     };
@@ -86,26 +484,78 @@ pub extern "C" fn mpclipboard_config_new(
     token: *const c_char,
     name: *const c_char,
 ) -> *mut Config {
-    let Ok(uri) = cstring_to_string(uri) else {
-        log::error!("invalid uri");
-        return std::ptr::null_mut();
+    clear_last_error();
+    let uri = match cstring_to_string(uri).context("invalid uri") {
+        Ok(uri) => uri,
+        Err(err) => {
+            set_last_error(ErrorCode::InvalidArgument, err);
+            return std::ptr::null_mut();
+        }
     };
-    let Ok(uri) = Uri::from_str(&uri) else {
-        log::error!("uri is invalid");
-        return std::ptr::null_mut();
+    let uri = match Uri::from_str(&uri).with_context(|| format!("uri {uri:?} is invalid")) {
+        Ok(uri) => uri,
+        Err(err) => {
+            set_last_error(ErrorCode::InvalidUri, err);
+            return std::ptr::null_mut();
+        }
     };
-    let Ok(token) = cstring_to_string(token) else {
-        log::error!("invalid token");
-        return std::ptr::null_mut();
+    let token = match cstring_to_string(token).context("invalid token") {
+        Ok(token) => token,
+        Err(err) => {
+            set_last_error(ErrorCode::InvalidArgument, err);
+            return std::ptr::null_mut();
+        }
     };
-    let Ok(name) = cstring_to_string(name) else {
-        log::error!("invalid name");
-        return std::ptr::null_mut();
+    let name = match cstring_to_string(name).context("invalid name") {
+        Ok(name) => name,
+        Err(err) => {
+            set_last_error(ErrorCode::InvalidArgument, err);
+            return std::ptr::null_mut();
+        }
     };
 
     Box::leak(Box::new(Config {
         uri,
         token: token.to_string(),
         name: name.to_string(),
+        ..Default::default()
     }))
 }
+
+#[unsafe(no_mangle)]
+/// Releases the config that hasn't been passed to
+/// `mpclipboard_thread_start` or `mpclipboard_handle_update_config`
+/// (both take ownership of it).
+///
+/// # Safety
+///
+/// `config` must be either NULL or a valid owned pointer to Config
+pub unsafe extern "C" fn mpclipboard_config_free(config: *mut Config) {
+    if !config.is_null() {
+        drop(unsafe { Box::from_raw(config) });
+    }
+}
+
+#[unsafe(no_mangle)]
+/// Sets a passphrase that enables end-to-end encryption of clips.
+///
+/// # Safety
+///
+/// `config` must be a valid pointer to Config
+/// `passphrase` must be a NULL terminated C string
+pub unsafe extern "C" fn mpclipboard_config_set_passphrase(
+    config: *mut Config,
+    passphrase: *const c_char,
+) -> bool {
+    clear_last_error();
+    let config = unsafe { &mut *config };
+    let passphrase = match cstring_to_string(passphrase).context("invalid passphrase") {
+        Ok(passphrase) => passphrase,
+        Err(err) => {
+            set_last_error(ErrorCode::InvalidArgument, err);
+            return false;
+        }
+    };
+    config.passphrase = Some(passphrase);
+    true
+}
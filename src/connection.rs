@@ -1,16 +1,44 @@
-use crate::{Config, clip::Clip, tls::TLS};
+use crate::{
+    Config, TransferConfig,
+    backoff::Backoff,
+    chunks::{self, Reassembled, Reassembler},
+    clip::Clip,
+    compression::{self, Compression},
+    crypto::Cipher,
+    progress::Progress,
+    queue::{Outgoing, Queue},
+    tls::TLS,
+};
 use anyhow::Result;
 use futures::{SinkExt as _, StreamExt as _, future::BoxFuture};
 use http::Uri;
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
-use tokio::{net::TcpStream, time::sleep};
+use std::{collections::VecDeque, future::poll_fn, time::Duration};
+use tokio::{
+    net::TcpStream,
+    time::{Instant, sleep_until, timeout_at},
+};
 use tokio_websockets::{ClientBuilder, Connector, MaybeTlsStream, Message, WebSocketStream};
 
 pub(crate) struct Connection {
     state: State,
+    // the whole attempt (including authentication) fails if it's not done by then
+    connect_deadline: Instant,
     config: Config,
-    pending: Option<Clip>,
+    queue: Queue,
+    cipher: Option<Cipher>,
+    uploading: Option<Outgoing>,
+    outgoing: VecDeque<(Message, Option<Progress>)>,
+    unacked: Vec<Outgoing>,
+    reassembler: Reassembler,
+    compression: Option<Compression>,
+    acks: bool,
+    backoff: Backoff,
+    // PING that is waiting to be sent and the one that is waiting for PONG
+    ping: Option<Message>,
+    ping_sent: Option<(u64, Instant)>,
+    // reports about queued clips that are returned from `recv` before anything else
+    reports: VecDeque<ConnectionEvent>,
 }
 
 enum State {
@@ -23,7 +51,7 @@ enum State {
     },
 
     WaitingForAuthResponse {
-        fut: BoxFuture<'static, Result<(bool, Conn), ()>>,
+        fut: BoxFuture<'static, Result<(AuthReply, Conn), ()>>,
     },
 
     Connected {
@@ -32,7 +60,12 @@ enum State {
 
     Disconnected {
         fut: BoxFuture<'static, ()>,
+        retry_at: Instant,
     },
+
+    // retrying with the same credentials is pointless,
+    // so nothing happens until they are updated
+    AuthFailed,
 }
 
 #[derive(Debug)]
@@ -42,66 +75,351 @@ pub(crate) enum ConnectionEvent {
     WaitingForAuthResponse,
     Connected,
     Disconnected,
-    AuthFailed,
+    AuthFailed(Option<String>),
     ReceivedPing,
+    ReceivedPong(Option<Duration>),
     ReceivedClip(Clip),
+    DecryptionFailed,
+    ClipRejected,
+    ClipSent,
+    UnknownAck,
+    ClipDelivered(Outgoing),
+    ClipFailed(Outgoing, String),
+    Progress(Progress),
 }
 
 type Conn = WebSocketStream<MaybeTlsStream<TcpStream>>;
 
 impl Connection {
-    pub(crate) fn new(config: Config) -> Self {
+    pub(crate) fn new(profile: &str, config: Config) -> Self {
+        if config.passphrase.is_some() {
+            log::info!("end-to-end encryption is enabled");
+        }
         Self {
             state: connecting(&config.uri),
+            connect_deadline: connect_deadline(&config),
+            cipher: config.passphrase.as_deref().map(Cipher::new),
+            queue: Queue::new(&config.queue, profile),
+            backoff: Backoff::new(&config.reconnect),
             config,
-            pending: None,
+            uploading: None,
+            outgoing: VecDeque::new(),
+            unacked: vec![],
+            reassembler: Reassembler::default(),
+            compression: None,
+            acks: false,
+            ping: None,
+            ping_sent: None,
+            reports: VecDeque::new(),
         }
     }
 
     pub(crate) fn disconnect(&mut self) {
-        if matches!(self.state, State::Disconnected { .. }) {
+        if matches!(self.state, State::Disconnected { .. } | State::AuthFailed) {
             return;
         }
-        self.state = disconnected()
+        self.set_disconnected();
     }
 
-    pub(crate) async fn send(&mut self, clip: Clip) {
-        let json = serde_json::to_string(&clip).expect("failed to serialize clip");
+    // Drops the connection for good, returns clips that haven't been delivered
+    pub(crate) fn close(mut self) -> Vec<Outgoing> {
+        self.reset_session();
+        self.queue.drain()
+    }
 
-        match &mut self.state {
-            State::Connected { conn, .. } => {
-                if let Err(err) = conn.send(Message::text(json)).await {
-                    log::error!("[ws] failed to send clip: {err:?}");
+    pub(crate) fn is_connected(&self) -> bool {
+        matches!(self.state, State::Connected { .. })
+    }
+
+    pub(crate) fn is_auth_failed(&self) -> bool {
+        matches!(self.state, State::AuthFailed)
+    }
+
+    // Returns `true` if the connection has been restarted to apply the new config
+    pub(crate) fn update_config(&mut self, config: Config) -> bool {
+        if config.passphrase != self.config.passphrase {
+            log::info!(
+                "end-to-end encryption is {}",
+                if config.passphrase.is_some() {
+                    "enabled"
+                } else {
+                    "disabled"
                 }
-            }
-            State::Connecting { .. }
-            | State::SendingAuthRequest { .. }
-            | State::WaitingForAuthResponse { .. }
-            | State::Disconnected { .. } => self.pending = Some(clip),
+            );
+            self.cipher = config.passphrase.as_deref().map(Cipher::new);
+        }
+        let dropped = self.queue.reconfigure(&config.queue);
+        self.report_dropped(dropped);
+        self.backoff.reconfigure(&config.reconnect);
+
+        let reconnect = !self.config.same_connection(&config);
+        self.config = config;
+        if !reconnect {
+            return false;
         }
+
+        log::info!("connection settings have changed, reconnecting");
+        self.reset_session();
+        self.backoff.reset();
+        self.connect();
+        true
     }
 
-    pub(crate) async fn send_pending_if_any(&mut self) {
-        let State::Connected { conn } = &mut self.state else {
+    // New token is used for all future connections, if the previous one
+    // has been rejected (or there's no connection) it's tried right away.
+    // Returns `true` if the state has changed
+    pub(crate) fn update_credentials(&mut self, token: String) -> bool {
+        self.config.token = token;
+        if self.is_auth_failed() {
+            log::info!("AuthFailed -> Connecting");
+            self.backoff.reset();
+            self.connect();
+            return true;
+        }
+        self.reconnect_now()
+    }
+
+    // PING is sent from `recv`, the round-trip time is reported once PONG comes back
+    pub(crate) fn ping(&mut self) {
+        if !self.is_connected() {
             return;
+        }
+        let id = fastrand::u64(..);
+        self.ping = Some(Message::ping(id.to_be_bytes().to_vec()));
+        self.ping_sent = Some((id, Instant::now()));
+    }
+
+    fn pong(&mut self, payload: &[u8]) -> ConnectionEvent {
+        let rtt = match self.ping_sent {
+            Some((id, sent_at)) if payload == id.to_be_bytes() => {
+                self.ping_sent = None;
+                Some(sent_at.elapsed())
+            }
+            _ => None,
         };
-        let Some(clip) = self.pending.as_mut() else {
-            return;
+        ConnectionEvent::ReceivedPong(rtt)
+    }
+
+    // Skips the pending backoff delay (if any) and starts connecting right away
+    // Returns `true` if the state has changed
+    pub(crate) fn reconnect_now(&mut self) -> bool {
+        self.backoff.reset();
+        if !matches!(self.state, State::Disconnected { .. }) {
+            return false;
+        }
+        log::info!("Disconnected -> Connecting (forced)");
+        self.connect();
+        true
+    }
+
+    fn connect(&mut self) {
+        self.connect_deadline = connect_deadline(&self.config);
+        self.state = connecting(&self.config.uri);
+    }
+
+    // Returns time left before the next reconnect attempt
+    pub(crate) fn retry_in(&self) -> Option<Duration> {
+        match &self.state {
+            State::Disconnected { retry_at, .. } => {
+                Some(retry_at.saturating_duration_since(Instant::now()))
+            }
+            _ => None,
+        }
+    }
+
+    fn set_disconnected(&mut self) {
+        let delay = self.backoff.next_delay();
+        log::info!("[ws] reconnecting in {delay:?}");
+        self.state = disconnected(delay);
+        self.reset_session();
+    }
+
+    fn set_auth_failed(&mut self) {
+        self.state = State::AuthFailed;
+        self.reset_session();
+    }
+
+    fn reset_session(&mut self) {
+        self.reassembler.clear();
+        self.outgoing.clear();
+        self.compression = None;
+        self.acks = false;
+        self.ping = None;
+        self.ping_sent = None;
+
+        // clips that might not have reached the server are sent again after reconnecting,
+        // so receivers may get a duplicate (that is dropped by their stores)
+        let mut interrupted = self.unacked.drain(..).collect::<Vec<_>>();
+        if let Some(item) = self.uploading.take() {
+            log::info!("[ws] upload has been interrupted, returning clip to the queue");
+            interrupted.push(item);
+        }
+        for item in interrupted.into_iter().rev() {
+            let dropped = self.queue.push_front(item);
+            self.report_dropped(dropped);
+        }
+    }
+
+    // Clips are queued and sent one by one in `recv` once there's a connection,
+    // this way the main loop is never blocked by a slow upload.
+    pub(crate) fn send(&mut self, item: Outgoing) {
+        let dropped = self.queue.push_back(item);
+        self.report_dropped(dropped);
+    }
+
+    fn report_dropped(&mut self, dropped: Vec<Outgoing>) {
+        for item in dropped {
+            self.reports.push_back(ConnectionEvent::ClipFailed(
+                item,
+                String::from("dropped from outbound queue"),
+            ));
+        }
+    }
+
+    fn start_upload(&mut self) -> bool {
+        while self.outgoing.is_empty() {
+            let Some(item) = self.queue.pop_front() else {
+                return false;
+            };
+            let Some(frames) = encode(
+                item.id,
+                &item.clip,
+                self.cipher.as_ref(),
+                self.compression,
+                &self.config.transfer,
+            ) else {
+                self.queue.complete(item.id);
+                self.reports.push_back(ConnectionEvent::ClipFailed(
+                    item,
+                    String::from("failed to encode clip"),
+                ));
+                continue;
+            };
+            if frames.len() > 1 {
+                log::info!("[ws] sending clip in {} chunks", frames.len());
+            }
+            self.outgoing = frames.into();
+            self.uploading = Some(item);
+        }
+        true
+    }
+
+    // Servers that don't acknowledge clips can't confirm anything,
+    // so a clip is considered delivered once it's written to the socket.
+    fn finish_upload(&mut self) -> ConnectionEvent {
+        let Some(item) = self.uploading.take() else {
+            return ConnectionEvent::ClipSent;
+        };
+        if self.acks {
+            self.unacked.push(item);
+            ConnectionEvent::ClipSent
+        } else {
+            self.queue.complete(item.id);
+            ConnectionEvent::ClipDelivered(item)
+        }
+    }
+
+    fn acknowledge(&mut self, ack: Ack) -> ConnectionEvent {
+        let Some(idx) = self.unacked.iter().position(|item| item.id == ack.ack) else {
+            log::warn!("[ws] received ack for unknown clip {}", ack.ack);
+            return ConnectionEvent::UnknownAck;
+        };
+        let item = self.unacked.remove(idx);
+        self.queue.complete(item.id);
+        match ack.error {
+            None => ConnectionEvent::ClipDelivered(item),
+            Some(err) => ConnectionEvent::ClipFailed(item, err),
+        }
+    }
+
+    async fn upload(&mut self) -> ConnectionEvent {
+        let State::Connected { conn } = &mut self.state else {
+            unreachable!("uploads only happen in Connected state");
         };
-        let json = serde_json::to_string(clip).expect("failed to serialize clip");
 
-        match conn.send(Message::text(json)).await {
-            Ok(()) => {
-                log::info!("[ws] pending clip has been sent");
-                self.pending = None
+        match send_frame(conn, &mut self.outgoing).await {
+            Ok(progress) => {
+                if self.outgoing.is_empty() {
+                    log::info!("[ws] clip has been sent");
+                    let event = self.finish_upload();
+                    let Some(progress) = progress else {
+                        return event;
+                    };
+                    self.reports.push_back(event);
+                    return ConnectionEvent::Progress(progress);
+                }
+                // only chunks of large clips report progress
+                progress.map_or(ConnectionEvent::ClipSent, ConnectionEvent::Progress)
             }
-            Err(err) => log::error!("[ws] failed to send clip: {err:?}"),
+            Err(err) => {
+                log::error!("[ws] failed to send clip: {err:?}");
+                log::info!("Connected -> Disconnected");
+                self.set_disconnected();
+                ConnectionEvent::Disconnected
+            }
+        }
+    }
+
+    // Returns `Err` if the message is malformed and the connection must be dropped
+    fn decode(&mut self, message: Message) -> Result<ConnectionEvent, ()> {
+        let max_size = self.config.transfer.max_clip_size;
+
+        let message = if message.is_binary() && chunks::is_chunk(message.as_payload()) {
+            match self.reassembler.push(message.as_payload(), max_size) {
+                Ok(Reassembled::InProgress(progress)) => {
+                    return Ok(ConnectionEvent::Progress(progress));
+                }
+                // chunks are never nested, a server that does it is either broken or hostile
+                Ok(Reassembled::Done(payload)) if chunks::is_chunk(&payload) => {
+                    log::error!("[chunks] reassembled clip is a chunk itself");
+                    return Ok(ConnectionEvent::ClipRejected);
+                }
+                Ok(Reassembled::Done(payload)) => Message::binary(payload),
+                Err(err) => {
+                    log::error!("[chunks] {err:?}");
+                    return Ok(ConnectionEvent::ClipRejected);
+                }
+            }
+        } else if let Some(ack) = message.as_text().and_then(parse_ack) {
+            return Ok(self.acknowledge(ack));
+        } else {
+            message
+        };
+
+        let clip = match decode_clip(&message, self.cipher.as_ref(), max_size)? {
+            Ok(clip) => clip,
+            Err(event) => return Ok(event),
+        };
+        if clip.size() > max_size {
+            log::error!(
+                "[ws] incoming clip is too large ({} bytes, max is {max_size})",
+                clip.size()
+            );
+            return Ok(ConnectionEvent::ClipRejected);
         }
+        Ok(ConnectionEvent::ReceivedClip(clip))
     }
 
     pub(crate) async fn recv(&mut self) -> ConnectionEvent {
+        if let Some(report) = self.reports.pop_front() {
+            return report;
+        }
+        if let State::Connected { conn } = &mut self.state
+            && self.ping.is_some()
+            && let Err(err) = send_ping(conn, &mut self.ping).await
+        {
+            log::error!("[ws] failed to send PING: {err:?}");
+            log::info!("Connected -> Disconnected");
+            self.set_disconnected();
+            return ConnectionEvent::Disconnected;
+        }
+        if self.is_connected() && self.start_upload() {
+            return self.upload().await;
+        }
+
+        let deadline = self.connect_deadline;
         match &mut self.state {
-            State::Connecting { fut } => match fut.await {
+            State::Connecting { fut } => match before(deadline, fut).await {
                 Ok(conn) => {
                     log::info!("Connecting -> SendingAuthRequest");
                     self.state = sending_auth_request(conn, &self.config);
@@ -109,12 +427,12 @@ impl Connection {
                 }
                 Err(()) => {
                     log::info!("Connecting -> Disconnected");
-                    self.state = disconnected();
+                    self.set_disconnected();
                     ConnectionEvent::Disconnected
                 }
             },
 
-            State::SendingAuthRequest { fut } => match fut.await {
+            State::SendingAuthRequest { fut } => match before(deadline, fut).await {
                 Ok(conn) => {
                     log::info!("SendingAuthRequest -> WaitingForAuthResponse");
                     self.state = waiting_for_auth_response(conn);
@@ -122,51 +440,81 @@ impl Connection {
                 }
                 Err(()) => {
                     log::info!("SendingAuthRequest -> Disconnected");
-                    self.state = disconnected();
+                    self.set_disconnected();
                     ConnectionEvent::Disconnected
                 }
             },
 
-            State::WaitingForAuthResponse { fut } => match fut.await {
-                Ok((true, conn)) => {
+            State::WaitingForAuthResponse { fut } => match before(deadline, fut).await {
+                Ok((reply, conn)) if reply.success => {
                     log::info!("WaitingForAuthResponse -> Connected");
+                    self.compression = reply.compression.filter(|_| self.config.transfer.compress);
+                    log::info!("[ws] negotiated compression: {:?}", self.compression);
+                    self.acks = reply.acks;
+                    log::info!("[ws] delivery acknowledgements: {}", self.acks);
                     self.state = State::Connected {
                         conn: Box::new(conn),
                     };
+                    self.backoff.connected();
                     ConnectionEvent::Connected
                 }
-                Ok((false, _)) => {
-                    log::info!("WaitingForAuthResponse -> Disconnected");
-                    self.state = disconnected();
-                    ConnectionEvent::AuthFailed
+                Ok((reply, _)) => {
+                    log::info!("WaitingForAuthResponse -> AuthFailed");
+                    log::error!(
+                        "[ws] server has rejected credentials: {}",
+                        reply.reason.as_deref().unwrap_or("no reason given")
+                    );
+                    self.set_auth_failed();
+                    ConnectionEvent::AuthFailed(reply.reason)
                 }
                 Err(()) => {
                     log::info!("WaitingForAuthResponse -> Disconnected");
-                    self.state = disconnected();
+                    self.set_disconnected();
                     ConnectionEvent::Disconnected
                 }
             },
 
-            State::Connected { conn } => match read_message(conn).await {
-                Ok(ConnectionMessage::Ping) => ConnectionEvent::ReceivedPing,
-                Ok(ConnectionMessage::Clip(clip)) => ConnectionEvent::ReceivedClip(clip),
-                Err(()) => {
-                    log::info!("Connected -> Disconnected");
-                    self.state = disconnected();
-                    ConnectionEvent::Disconnected
+            State::Connected { conn } => {
+                let event = match read_message(conn).await {
+                    Ok(ConnectionMessage::Ping) => Ok(ConnectionEvent::ReceivedPing),
+                    Ok(ConnectionMessage::Pong(payload)) => Ok(self.pong(&payload)),
+                    Ok(ConnectionMessage::Data(message)) => self.decode(message),
+                    Err(()) => Err(()),
+                };
+                match event {
+                    Ok(event) => event,
+                    Err(()) => {
+                        log::info!("Connected -> Disconnected");
+                        self.set_disconnected();
+                        ConnectionEvent::Disconnected
+                    }
                 }
-            },
+            }
 
-            State::Disconnected { fut } => {
+            State::Disconnected { fut, .. } => {
                 fut.await;
                 log::info!("Disconnected -> Connecting");
-                self.state = connecting(&self.config.uri);
+                self.connect();
                 ConnectionEvent::Connecting
             }
+
+            State::AuthFailed => std::future::pending().await,
         }
     }
 }
 
+fn connect_deadline(config: &Config) -> Instant {
+    Instant::now() + Duration::from_secs(config.heartbeat.connect_timeout_secs)
+}
+
+// Fails a step of the connection attempt once the attempt's deadline has passed
+async fn before<T>(deadline: Instant, fut: impl Future<Output = Result<T, ()>>) -> Result<T, ()> {
+    timeout_at(deadline, fut).await.unwrap_or_else(|_| {
+        log::error!("[ws] connection attempt has timed out");
+        Err(())
+    })
+}
+
 fn connecting(uri: &Uri) -> State {
     async fn async_impl(uri: Uri) -> Result<Conn, ()> {
         log::info!("Connecting to {uri}");
@@ -209,11 +557,20 @@ fn sending_auth_request(conn: Conn, config: &Config) -> State {
         pub(crate) struct Auth {
             pub(crate) name: String,
             pub(crate) token: String,
+            #[serde(skip_serializing_if = "<[_]>::is_empty")]
+            pub(crate) compression: &'static [Compression],
+            pub(crate) acks: bool,
         }
 
         let auth = Auth {
             name: config.name,
             token: config.token,
+            compression: if config.transfer.compress {
+                Compression::SUPPORTED
+            } else {
+                &[]
+            },
+            acks: true,
         };
         let Ok(json) = serde_json::to_string(&auth) else {
             log::error!("malformed name/token");
@@ -234,8 +591,36 @@ fn sending_auth_request(conn: Conn, config: &Config) -> State {
     }
 }
 
+#[derive(Deserialize)]
+struct AuthReply {
+    success: bool,
+    // compression algorithm picked by the server out of the ones we've offered,
+    // servers that don't support compression simply don't send it
+    #[serde(default)]
+    compression: Option<Compression>,
+    // servers that don't acknowledge delivered clips reply without this flag
+    #[serde(default)]
+    acks: bool,
+    // why credentials have been rejected (if the server tells)
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+// Sent by the server once a clip with given id has been relayed
+// (or with `error` if the server refused to relay it)
+#[derive(Deserialize)]
+struct Ack {
+    ack: u64,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+fn parse_ack(text: &str) -> Option<Ack> {
+    serde_json::from_str::<Ack>(text).ok()
+}
+
 fn waiting_for_auth_response(conn: Conn) -> State {
-    async fn async_impl(mut conn: Conn) -> Result<(bool, Conn), ()> {
+    async fn async_impl(mut conn: Conn) -> Result<(AuthReply, Conn), ()> {
         let message = conn.next().await;
         let Some(message) = message else {
             return Err(());
@@ -252,13 +637,8 @@ fn waiting_for_auth_response(conn: Conn) -> State {
             return Err(());
         };
 
-        #[derive(Deserialize)]
-        struct AuthReply {
-            success: bool,
-        }
-
         match serde_json::from_str::<AuthReply>(message) {
-            Ok(reply) => Ok((reply.success, conn)),
+            Ok(reply) => Ok((reply, conn)),
             Err(err) => {
                 log::error!("[ws] failed to parse AuthReply: {err:?}");
                 Err(())
@@ -271,9 +651,35 @@ fn waiting_for_auth_response(conn: Conn) -> State {
     }
 }
 
+// `recv` can be cancelled at any `.await` point, so the frame is taken from the queue
+// only when the sink is ready to accept it; if flushing gets cancelled the frame
+// stays buffered in the sink and is flushed together with the next one.
+async fn send_frame(
+    conn: &mut Conn,
+    outgoing: &mut VecDeque<(Message, Option<Progress>)>,
+) -> Result<Option<Progress>, tokio_websockets::Error> {
+    poll_fn(|cx| conn.poll_ready_unpin(cx)).await?;
+    let (frame, progress) = outgoing.pop_front().expect("outgoing queue is not empty");
+    conn.start_send_unpin(frame)?;
+    poll_fn(|cx| conn.poll_flush_unpin(cx)).await?;
+    Ok(progress)
+}
+
+// Same as `send_frame`, but for a single control frame
+async fn send_ping(
+    conn: &mut Conn,
+    ping: &mut Option<Message>,
+) -> Result<(), tokio_websockets::Error> {
+    poll_fn(|cx| conn.poll_ready_unpin(cx)).await?;
+    let frame = ping.take().expect("PING is set");
+    conn.start_send_unpin(frame)?;
+    poll_fn(|cx| conn.poll_flush_unpin(cx)).await
+}
+
 pub(crate) enum ConnectionMessage {
     Ping,
-    Clip(Clip),
+    Pong(Vec<u8>),
+    Data(Message),
 }
 
 async fn read_message(conn: &mut Conn) -> Result<ConnectionMessage, ()> {
@@ -292,14 +698,101 @@ async fn read_message(conn: &mut Conn) -> Result<ConnectionMessage, ()> {
     if message.is_ping() {
         return Ok(ConnectionMessage::Ping);
     }
+    if message.is_pong() {
+        return Ok(ConnectionMessage::Pong(message.as_payload().to_vec()));
+    }
 
-    let Some(message) = message.as_text() else {
-        log::error!("[ws] received message is neither PING nor TEXT");
+    if !message.is_text() && !message.is_binary() {
+        log::error!("[ws] received message is neither PING/PONG nor TEXT nor BINARY");
         return Err(());
+    }
+
+    Ok(ConnectionMessage::Data(message))
+}
+
+// Returns `None` if clip can't be encrypted,
+// otherwise a list of frames, chunks also carry the progress they report once sent.
+// The id is visible to the server in both formats: as an `id` field of a JSON (text) clip
+// and as a transfer id of binary chunks (small binary clips are sent as a single chunk).
+fn encode(
+    id: u64,
+    clip: &Clip,
+    cipher: Option<&Cipher>,
+    compression: Option<Compression>,
+    config: &TransferConfig,
+) -> Option<Vec<(Message, Option<Progress>)>> {
+    let chunk_size = config.chunk_size;
+    // compression goes first, encrypted data is not compressible
+    let compression = compression.filter(|_| clip.size() >= config.compression_threshold);
+    let binary = || match compression {
+        Some(compression) => compression.compress(&clip.to_binary()),
+        None => clip.to_binary(),
     };
 
-    match serde_json::from_str::<Clip>(message) {
-        Ok(clip) => Ok(ConnectionMessage::Clip(clip)),
+    let payload = if let Some(cipher) = cipher {
+        match cipher.encrypt(&binary()) {
+            Ok(ciphertext) => ciphertext,
+            Err(err) => {
+                log::error!("[e2e] failed to encrypt clip: {err:?}");
+                return None;
+            }
+        }
+    } else if clip.is_plain_text() && compression.is_none() {
+        #[derive(Serialize)]
+        struct TextClip<'a> {
+            id: u64,
+            #[serde(flatten)]
+            clip: &'a Clip,
+        }
+
+        let json = serde_json::to_string(&TextClip { id, clip }).expect("failed to serialize clip");
+        if json.len() <= chunk_size {
+            return Some(vec![(Message::text(json), None)]);
+        }
+        clip.to_binary()
+    } else {
+        binary()
+    };
+
+    let chunks = chunks::split(&payload, chunk_size, id);
+    let single = chunks.len() == 1;
+    Some(
+        chunks
+            .into_iter()
+            .map(|(chunk, progress)| (Message::binary(chunk), Some(progress).filter(|_| !single)))
+            .collect(),
+    )
+}
+
+// Outer `Err` means a malformed message, inner `Err` is an event
+// that replaces the clip (but keeps the connection).
+fn decode_clip(
+    message: &Message,
+    cipher: Option<&Cipher>,
+    max_size: usize,
+) -> Result<Result<Clip, ConnectionEvent>, ()> {
+    // A clip that can't be decrypted is reported and skipped,
+    // it's either a misconfigured client or a server injecting data.
+    if let Some(cipher) = cipher {
+        if !message.is_binary() {
+            log::error!("[e2e] received unencrypted clip, ignoring");
+            return Ok(Err(ConnectionEvent::DecryptionFailed));
+        }
+        return Ok(cipher
+            .decrypt(message.as_payload())
+            .and_then(|plaintext| decode_binary(&plaintext, max_size))
+            .map_err(|err| {
+                log::error!("[e2e] failed to decrypt clip: {err:?}");
+                ConnectionEvent::DecryptionFailed
+            }));
+    }
+
+    let clip = match message.as_text() {
+        Some(text) => serde_json::from_str::<Clip>(text).map_err(anyhow::Error::from),
+        None => decode_binary(message.as_payload(), max_size),
+    };
+    match clip {
+        Ok(clip) => Ok(Ok(clip)),
         Err(err) => {
             log::error!("[ws] failed to parse clip: {err:?}");
             Err(())
@@ -307,11 +800,19 @@ async fn read_message(conn: &mut Conn) -> Result<ConnectionMessage, ()> {
     }
 }
 
-fn disconnected() -> State {
-    async fn async_impl() {
-        sleep(Duration::from_secs(5)).await
+fn decode_binary(bytes: &[u8], max_size: usize) -> Result<Clip> {
+    if compression::is_compressed(bytes) {
+        // leaves some room for the header of the binary clip
+        let decompressed = compression::decompress(bytes, max_size.saturating_add(64 * 1024))?;
+        return Clip::from_binary(&decompressed);
     }
+    Clip::from_binary(bytes)
+}
+
+fn disconnected(delay: Duration) -> State {
+    let retry_at = Instant::now() + delay;
     State::Disconnected {
-        fut: Box::pin(async_impl()),
+        fut: Box::pin(sleep_until(retry_at)),
+        retry_at,
     }
 }
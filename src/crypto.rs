@@ -0,0 +1,121 @@
+use anyhow::{Result, anyhow, bail};
+use ring::{
+    aead::{Aad, CHACHA20_POLY1305, LessSafeKey, NONCE_LEN, Nonce, UnboundKey},
+    hmac, pbkdf2,
+    rand::{SecureRandom as _, SystemRandom},
+};
+use std::num::NonZeroU32;
+
+// Envelope layout:
+// [MAGIC][nonce][ciphertext + tag]
+const MAGIC: &[u8] = b"MPCE1";
+const SALT: &[u8] = b"mpclipboard-e2e-v1";
+const SIGNER_SALT: &[u8] = b"mpclipboard-signer-v1";
+const ITERATIONS: NonZeroU32 = NonZeroU32::new(100_000).unwrap();
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> [u8; 32] {
+    let mut key = [0; 32];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        ITERATIONS,
+        salt,
+        passphrase.as_bytes(),
+        &mut key,
+    );
+    key
+}
+
+pub(crate) struct Cipher {
+    key: LessSafeKey,
+    rng: SystemRandom,
+}
+
+impl Cipher {
+    pub(crate) fn new(passphrase: &str) -> Self {
+        let key = derive_key(passphrase, SALT);
+        let key = UnboundKey::new(&CHACHA20_POLY1305, &key).expect("key length is always valid");
+
+        Self {
+            key: LessSafeKey::new(key),
+            rng: SystemRandom::new(),
+        }
+    }
+
+    pub(crate) fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let mut nonce = [0; NONCE_LEN];
+        self.rng
+            .fill(&mut nonce)
+            .map_err(|_| anyhow!("failed to generate nonce"))?;
+
+        let mut in_out = plaintext.to_vec();
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(MAGIC),
+                &mut in_out,
+            )
+            .map_err(|_| anyhow!("failed to encrypt"))?;
+
+        let mut out = Vec::with_capacity(MAGIC.len() + NONCE_LEN + in_out.len());
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&in_out);
+        Ok(out)
+    }
+
+    pub(crate) fn decrypt(&self, envelope: &[u8]) -> Result<Vec<u8>> {
+        let Some(rest) = envelope.strip_prefix(MAGIC) else {
+            bail!("not an encrypted clip");
+        };
+        let Some((nonce, ciphertext)) = rest.split_first_chunk::<NONCE_LEN>() else {
+            bail!("encrypted clip is too short");
+        };
+
+        let mut in_out = ciphertext.to_vec();
+        let plaintext = self
+            .key
+            .open_in_place(
+                Nonce::assume_unique_for_key(*nonce),
+                Aad::from(MAGIC),
+                &mut in_out,
+            )
+            .map_err(|_| anyhow!("failed to decrypt (wrong passphrase?)"))?;
+        Ok(plaintext.to_vec())
+    }
+}
+
+// Authenticates messages that are sent in plaintext (e.g. LAN beacons),
+// the key is derived from the same passphrase but differs from the encryption key.
+pub(crate) struct Signer {
+    key: hmac::Key,
+}
+
+impl Signer {
+    pub(crate) const TAG_LEN: usize = 32;
+
+    pub(crate) fn new(passphrase: &str) -> Self {
+        let key = derive_key(passphrase, SIGNER_SALT);
+        Self {
+            key: hmac::Key::new(hmac::HMAC_SHA256, &key),
+        }
+    }
+
+    // Returns the message prefixed with its tag
+    pub(crate) fn sign(&self, message: &[u8]) -> Vec<u8> {
+        let tag = hmac::sign(&self.key, message);
+        let mut out = Vec::with_capacity(Self::TAG_LEN + message.len());
+        out.extend_from_slice(tag.as_ref());
+        out.extend_from_slice(message);
+        out
+    }
+
+    // Returns the message if its tag is valid
+    pub(crate) fn verify<'a>(&self, signed: &'a [u8]) -> Option<&'a [u8]> {
+        if signed.len() < Self::TAG_LEN {
+            return None;
+        }
+        let (tag, message) = signed.split_at(Self::TAG_LEN);
+        hmac::verify(&self.key, message, tag).ok()?;
+        Some(message)
+    }
+}
@@ -0,0 +1,112 @@
+use crate::clip::Clip;
+use anyhow::{Context as _, Result};
+use std::{
+    fs::{File, OpenOptions},
+    io::Write as _,
+    path::{Path, PathBuf},
+};
+
+pub(crate) struct Disk {
+    path: PathBuf,
+    lines: usize,
+}
+
+impl Disk {
+    pub(crate) fn open(path: Option<&Path>, default_file_name: &str) -> Result<Self> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => data_dir()?.join(default_file_name),
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create {}", dir.display()))?;
+        }
+        Ok(Self { path, lines: 0 })
+    }
+
+    pub(crate) fn lines(&self) -> usize {
+        self.lines
+    }
+
+    // Lines that can't be parsed (including ones that aren't valid UTF-8) are skipped,
+    // the rest of the file is still loaded
+    pub(crate) fn load(&mut self) -> Result<Vec<Clip>> {
+        let content = match std::fs::read(&self.path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => {
+                return Err(err).with_context(|| format!("failed to read {}", self.path.display()));
+            }
+        };
+
+        let mut clips = vec![];
+        for (idx, line) in content.split(|byte| *byte == b'\n').enumerate() {
+            if line.trim_ascii().is_empty() {
+                continue;
+            }
+            match serde_json::from_slice::<Clip>(line) {
+                Ok(clip) => clips.push(clip),
+                Err(err) => log::warn!("[disk] skipping corrupted line {}: {err:?}", idx + 1),
+            }
+        }
+        Ok(clips)
+    }
+
+    pub(crate) fn append(&mut self, clip: &Clip) -> Result<()> {
+        let mut line = serde_json::to_string(clip).context("failed to serialize clip")?;
+        line.push('\n');
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("failed to open {}", self.path.display()))?;
+        // single write of a whole line, so a crash can only leave
+        // a truncated last line that gets skipped on load
+        file.write_all(line.as_bytes())
+            .with_context(|| format!("failed to write {}", self.path.display()))?;
+        self.lines += 1;
+        Ok(())
+    }
+
+    pub(crate) fn clear(&mut self) -> Result<()> {
+        File::create(&self.path)
+            .with_context(|| format!("failed to truncate {}", self.path.display()))?;
+        self.lines = 0;
+        Ok(())
+    }
+
+    pub(crate) fn rewrite<'a>(&mut self, clips: impl Iterator<Item = &'a Clip>) -> Result<()> {
+        let mut content = String::new();
+        let mut lines = 0;
+        for clip in clips {
+            content.push_str(&serde_json::to_string(clip).context("failed to serialize clip")?);
+            content.push('\n');
+            lines += 1;
+        }
+
+        let tmp = self.path.with_extension("tmp");
+        let mut file =
+            File::create(&tmp).with_context(|| format!("failed to create {}", tmp.display()))?;
+        file.write_all(content.as_bytes())
+            .and_then(|_| file.sync_all())
+            .with_context(|| format!("failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, &self.path)
+            .with_context(|| format!("failed to replace {}", self.path.display()))?;
+
+        self.lines = lines;
+        Ok(())
+    }
+}
+
+fn data_dir() -> Result<PathBuf> {
+    let data_dir = match std::env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => {
+            let home =
+                std::env::var_os("HOME").context("neither $XDG_DATA_HOME nor $HOME is set")?;
+            PathBuf::from(home).join(".local/share")
+        }
+    };
+    Ok(data_dir.join("mpclipboard"))
+}
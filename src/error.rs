@@ -0,0 +1,112 @@
+use crate::ffi::string_to_cstring;
+use std::{cell::RefCell, ffi::c_char};
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Kind of the last error, see `mpclipboard_last_error_code`
+pub enum ErrorCode {
+    /// The last fallible call on this thread hasn't failed
+    None = 0,
+    /// Argument is invalid (e.g. a string is not valid UTF-8 or a list is empty)
+    InvalidArgument = 1,
+    /// File doesn't exist (e.g. there's no config file)
+    NotFound = 2,
+    /// Any other I/O error (e.g. permission denied)
+    Io = 3,
+    /// Config is not a valid TOML or has missing/invalid fields
+    InvalidConfig = 4,
+    /// URI of the server is malformed
+    InvalidUri = 5,
+    /// TLS connector can't be initialized
+    Tls = 6,
+    /// Background thread is not running (e.g. it has crashed)
+    ThreadNotRunning = 7,
+    /// Background thread can't be stopped gracefully
+    ThreadStop = 8,
+    /// Clip is larger than `transfer.max_clip_size` of the config
+    ClipTooLarge = 9,
+}
+
+impl ErrorCode {
+    // Errors that have a more specific code than the one picked by the caller
+    fn classify(err: &anyhow::Error) -> Option<Self> {
+        err.chain().find_map(|cause| {
+            if let Some(err) = cause.downcast_ref::<std::io::Error>() {
+                if err.kind() == std::io::ErrorKind::NotFound {
+                    Some(Self::NotFound)
+                } else {
+                    Some(Self::Io)
+                }
+            } else if cause.is::<toml::de::Error>() {
+                Some(Self::InvalidConfig)
+            } else if cause.is::<http::uri::InvalidUri>() {
+                Some(Self::InvalidUri)
+            } else if cause.is::<ClipTooLarge>() {
+                Some(Self::ClipTooLarge)
+            } else if cause.is::<std::env::VarError>() {
+                // e.g. there's no $HOME, so the config file can't be found
+                Some(Self::NotFound)
+            } else {
+                None
+            }
+        })
+    }
+}
+
+// Local clip that is rejected before it's passed to the background thread
+#[derive(Debug)]
+pub(crate) struct ClipTooLarge {
+    pub(crate) size: usize,
+    pub(crate) max: usize,
+}
+
+impl std::fmt::Display for ClipTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "clip is too large ({} bytes, max is {})",
+            self.size, self.max
+        )
+    }
+}
+
+impl std::error::Error for ClipTooLarge {}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<(ErrorCode, String)>> = const { RefCell::new(None) };
+}
+
+// Logs the error and remembers it as the last error of the current thread,
+// `code` is used if the error chain doesn't have a more specific cause.
+pub(crate) fn set_last_error(code: ErrorCode, err: anyhow::Error) {
+    log::error!("{err:?}");
+    let code = ErrorCode::classify(&err).unwrap_or(code);
+    LAST_ERROR.with_borrow_mut(|last| *last = Some((code, format!("{err:#}"))));
+}
+
+// Called at the beginning of every fallible function, so `ErrorCode::None`
+// after a call means that the call itself hasn't failed
+pub(crate) fn clear_last_error() {
+    LAST_ERROR.with_borrow_mut(|last| *last = None);
+}
+
+#[unsafe(no_mangle)]
+/// Returns the kind of the error reported by the last fallible call on the calling thread.
+/// Every fallible function resets it, so it's `None` if the last call hasn't failed
+/// (e.g. `mpclipboard_handle_send` has returned `false` because the text is a duplicate).
+pub extern "C" fn mpclipboard_last_error_code() -> ErrorCode {
+    LAST_ERROR.with_borrow(|last| last.as_ref().map_or(ErrorCode::None, |(code, _)| *code))
+}
+
+#[unsafe(no_mangle)]
+/// Returns a human-readable description of the error reported by the last
+/// fallible call on the calling thread (including all its causes, e.g.
+/// `"failed to read config.toml: No such file or directory (os error 2)"`)
+/// or NULL if there were no errors.
+/// Returned string must be released with `mpclipboard_string_free`.
+pub extern "C" fn mpclipboard_last_error_message() -> *mut c_char {
+    LAST_ERROR.with_borrow(|last| match last {
+        Some((_, message)) => string_to_cstring(message.clone()),
+        None => std::ptr::null_mut(),
+    })
+}
@@ -1,6 +1,81 @@
-use crate::clip::Clip;
+use crate::{clip::Clip, progress::Progress, status::ConnectionStatus};
+use futures::Stream;
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::sync::mpsc::UnboundedReceiver;
 
-pub(crate) enum Event {
-    ConnectivityChanged(bool),
+/// Single update from the background thread
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// Overall status of connections has changed (the most "alive" one
+    /// if there are several servers, see `ProfileStatusChanged` for details)
+    StatusChanged(ConnectionStatus),
+    /// Status of the connection to a single server profile has changed
+    /// (`"default"` for the main server)
+    ProfileStatusChanged(String, ConnectionStatus),
+    /// Server has rejected credentials (with a reason if the server has sent it),
+    /// see `Handle::update_credentials`
+    AuthFailed(Option<String>),
+    /// New clip has been received from the server
     NewClip(Clip),
+    /// Progress of a large clip transfer
+    Progress(Progress),
+    /// Round-trip time to the server (measured with client pings)
+    Latency(Duration),
+    /// Local clip has been confirmed by the server
+    ClipDelivered(Clip),
+    /// Local clip couldn't be delivered
+    ClipFailed(Clip),
+    /// Received clip (from the server or LAN) couldn't be decrypted,
+    /// most likely passphrases don't match
+    DecryptionFailed,
+    /// Received clip has been dropped because it's malformed or too large
+    ClipRejected,
+}
+
+/// Updates squashed by `Handle::recv`
+#[derive(Debug, Default)]
+pub struct Update {
+    /// The latest clip received from the server
+    pub clip: Option<Clip>,
+    /// The latest change of the connectivity
+    /// (it's set only when the connection is made or lost, see `status` for details)
+    pub connectivity: Option<bool>,
+    /// The latest overall status of connections
+    pub status: Option<ConnectionStatus>,
+    /// The latest status of every server profile that has changed
+    pub profiles: HashMap<String, ConnectionStatus>,
+    /// Reason of the latest authentication failure (if the server has sent it),
+    /// see `Handle::update_credentials`
+    pub auth_error: Option<String>,
+    /// The latest round-trip time to the server (measured with client pings)
+    pub latency: Option<Duration>,
+    /// The latest progress of a large clip transfer
+    pub progress: Option<Progress>,
+    /// Local clips confirmed by the server, in order of delivery
+    pub delivered: Vec<Clip>,
+    /// Local clips that couldn't be delivered
+    pub failed: Vec<Clip>,
+    /// Set if a received clip couldn't be decrypted (most likely passphrases don't match)
+    pub decryption_failed: bool,
+    /// Set if a received clip has been dropped because it's malformed or too large
+    pub clip_rejected: bool,
+}
+
+/// Stream of every event in order (nothing is squashed), see `Handle::events`.
+/// Ends when the background thread stops.
+pub struct EventStream {
+    pub(crate) erx: UnboundedReceiver<Event>,
+}
+
+impl Stream for EventStream {
+    type Item = Event;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        self.erx.poll_recv(cx)
+    }
 }
@@ -0,0 +1,193 @@
+use crate::{
+    ConnectionState, ConnectionStatus, Direction, Event, Progress,
+    clip::Clip,
+    ffi::{flavor_to_ptrs, free_bytes, free_cstring, string_to_cstring},
+};
+use std::ffi::c_char;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Kind of an event, defines which fields of `EventItem` are set
+pub enum EventKind {
+    /// Overall status of connections has changed, `status` is set
+    StatusChanged = 0,
+    /// Server has rejected credentials, `auth_error` is set if the server has sent a reason
+    AuthFailed = 1,
+    /// New clip has been received from the server, `text` or `mime` + `data` + `len` are set
+    NewClip = 2,
+    /// Progress of a large clip transfer, `progress` is set
+    Progress = 3,
+    /// Round-trip time to the server, `latency_ms` is set
+    Latency = 4,
+    /// Local clip has been confirmed by the server, `text` or `mime` + `data` + `len` are set
+    ClipDelivered = 5,
+    /// Local clip couldn't be delivered, `text` or `mime` + `data` + `len` are set
+    ClipFailed = 6,
+    /// Status of the connection to a single server has changed, `profile` and `status` are set
+    ProfileStatusChanged = 7,
+    /// Received clip couldn't be decrypted (most likely passphrases don't match), no fields are set
+    DecryptionFailed = 8,
+    /// Received clip has been dropped because it's malformed or too large, no fields are set
+    ClipRejected = 9,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+/// Single event, fields that don't belong to its `kind` are NULL/zero
+pub struct EventItem {
+    /// Kind of the event
+    pub kind: EventKind,
+    /// Text of the clip, set only if the best supported flavor is `text/plain`
+    pub text: *mut c_char,
+    /// MIME type of the best supported non-text flavor of the clip
+    pub mime: *mut c_char,
+    /// Content of the best supported non-text flavor of the clip
+    pub data: *mut u8,
+    /// Length of `data`
+    pub len: usize,
+    /// Status of the connection
+    pub status: ConnectionStatus,
+    /// Name of the server profile (`"default"` for the main server)
+    pub profile: *mut c_char,
+    /// Reason of the authentication failure
+    pub auth_error: *mut c_char,
+    /// Progress of a large clip transfer
+    pub progress: Progress,
+    /// Round-trip time to the server in milliseconds
+    pub latency_ms: u64,
+}
+
+impl EventItem {
+    fn empty(kind: EventKind) -> Self {
+        Self {
+            kind,
+            text: std::ptr::null_mut(),
+            mime: std::ptr::null_mut(),
+            data: std::ptr::null_mut(),
+            len: 0,
+            status: ConnectionStatus {
+                state: ConnectionState::Disconnected,
+                retry_in_ms: 0,
+            },
+            profile: std::ptr::null_mut(),
+            auth_error: std::ptr::null_mut(),
+            progress: Progress {
+                direction: Direction::Upload,
+                transferred: 0,
+                total: 0,
+            },
+            latency_ms: 0,
+        }
+    }
+
+    // Returns `None` for received clips that have no supported flavors
+    fn new(event: Event, supported: &[String]) -> Option<Self> {
+        let item = match event {
+            Event::StatusChanged(status) => Self {
+                status,
+                ..Self::empty(EventKind::StatusChanged)
+            },
+            Event::ProfileStatusChanged(profile, status) => Self {
+                profile: string_to_cstring(profile),
+                status,
+                ..Self::empty(EventKind::ProfileStatusChanged)
+            },
+            Event::AuthFailed(reason) => Self {
+                auth_error: reason.map_or(std::ptr::null_mut(), string_to_cstring),
+                ..Self::empty(EventKind::AuthFailed)
+            },
+            Event::NewClip(clip) => {
+                if clip.best_flavor(supported).is_none() {
+                    log::warn!("none of the flavors of {clip:?} is supported, skipping");
+                    return None;
+                }
+                Self::with_clip(EventKind::NewClip, &clip, supported)
+            }
+            Event::Progress(progress) => Self {
+                progress,
+                ..Self::empty(EventKind::Progress)
+            },
+            Event::Latency(latency) => Self {
+                latency_ms: latency.as_millis() as u64,
+                ..Self::empty(EventKind::Latency)
+            },
+            Event::ClipDelivered(clip) => {
+                Self::with_clip(EventKind::ClipDelivered, &clip, supported)
+            }
+            Event::ClipFailed(clip) => Self::with_clip(EventKind::ClipFailed, &clip, supported),
+            Event::DecryptionFailed => Self::empty(EventKind::DecryptionFailed),
+            Event::ClipRejected => Self::empty(EventKind::ClipRejected),
+        };
+        Some(item)
+    }
+
+    fn with_clip(kind: EventKind, clip: &Clip, supported: &[String]) -> Self {
+        let mut item = Self::empty(kind);
+        if let Some(flavor) = clip.best_flavor(supported) {
+            let flavor = flavor_to_ptrs(flavor.clone());
+            item.text = flavor.text;
+            item.mime = flavor.mime;
+            item.data = flavor.data;
+            item.len = flavor.len;
+        }
+        item
+    }
+}
+
+#[repr(C)]
+#[derive(Debug)]
+/// Owned array of events in the order they have happened.
+/// Must be released with `mpclipboard_event_list_free`.
+pub struct EventList {
+    /// Pointer to the first event, NULL if there are no events
+    pub items: *mut EventItem,
+    /// Number of events
+    pub len: usize,
+}
+
+impl EventList {
+    pub(crate) fn new(events: Vec<Event>, supported: &[String]) -> Self {
+        let items = events
+            .into_iter()
+            .filter_map(|event| EventItem::new(event, supported))
+            .collect::<Box<[_]>>();
+        if items.is_empty() {
+            return Self {
+                items: std::ptr::null_mut(),
+                len: 0,
+            };
+        }
+
+        let len = items.len();
+
+        Self {
+            items: Box::leak(items).as_mut_ptr(),
+            len,
+        }
+    }
+}
+
+/// Releases events returned by `mpclipboard_handle_poll_many`
+///
+/// # Safety
+///
+/// `events` must be a value returned by `mpclipboard_handle_poll_many`
+/// that hasn't been released yet
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mpclipboard_event_list_free(events: EventList) {
+    if events.items.is_null() {
+        return;
+    }
+
+    let items =
+        unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(events.items, events.len)) };
+    for item in items {
+        unsafe {
+            free_cstring(item.text);
+            free_cstring(item.mime);
+            free_bytes(item.data, item.len);
+            free_cstring(item.profile);
+            free_cstring(item.auth_error);
+        }
+    }
+}
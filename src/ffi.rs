@@ -1,14 +1,34 @@
+use crate::clip::Flavor;
 use anyhow::{Context as _, Result};
 use std::ffi::c_char;
 
+pub(crate) struct FlavorPtrs {
+    pub(crate) text: *mut c_char,
+    pub(crate) mime: *mut c_char,
+    pub(crate) data: *mut u8,
+    pub(crate) len: usize,
+}
+
+// `text/plain` goes to `text`, anything else goes to `mime` + `data` + `len`
+pub(crate) fn flavor_to_ptrs(flavor: Flavor) -> FlavorPtrs {
+    let mut out = FlavorPtrs {
+        text: std::ptr::null_mut(),
+        mime: std::ptr::null_mut(),
+        data: std::ptr::null_mut(),
+        len: 0,
+    };
+    if let Some(text) = flavor.as_text() {
+        out.text = string_to_cstring(text.to_string());
+    } else {
+        out.mime = string_to_cstring(flavor.mime);
+        (out.data, out.len) = bytes_to_ptr(flavor.data);
+    }
+    out
+}
+
 pub(crate) fn string_to_cstring(s: String) -> *mut c_char {
     match std::ffi::CString::new(s) {
-        Ok(text) => {
-            let mut bytes = text.into_bytes_with_nul();
-            let ptr = bytes.as_mut_ptr();
-            std::mem::forget(bytes);
-            ptr.cast()
-        }
+        Ok(text) => text.into_raw(),
         Err(_) => {
             log::error!("clip text is NULL terminated");
             std::ptr::null_mut()
@@ -16,9 +36,44 @@ pub(crate) fn string_to_cstring(s: String) -> *mut c_char {
     }
 }
 
+pub(crate) fn bytes_to_ptr(bytes: Vec<u8>) -> (*mut u8, usize) {
+    if bytes.is_empty() {
+        return (std::ptr::null_mut(), 0);
+    }
+    let bytes = bytes.into_boxed_slice();
+    let len = bytes.len();
+    (Box::leak(bytes).as_mut_ptr(), len)
+}
+
+// Counterpart of `string_to_cstring`, NULL is ignored
+pub(crate) unsafe fn free_cstring(s: *mut c_char) {
+    if !s.is_null() {
+        drop(unsafe { std::ffi::CString::from_raw(s) });
+    }
+}
+
+// Counterpart of `bytes_to_ptr`, NULL is ignored
+pub(crate) unsafe fn free_bytes(ptr: *mut u8, len: usize) {
+    if !ptr.is_null() {
+        drop(unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len)) });
+    }
+}
+
 pub(crate) fn cstring_to_string(s: *const c_char) -> Result<String> {
     Ok(unsafe { std::ffi::CStr::from_ptr(s) }
         .to_str()
         .context("failed to convert *char to String")?
         .to_string())
 }
+
+/// Releases a string allocated by MPClipboard
+/// (e.g. a field of `mpclipboard_output_t` taken over by the caller)
+///
+/// # Safety
+///
+/// `s` must be either NULL or a string allocated by MPClipboard
+/// that hasn't been released yet
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mpclipboard_string_free(s: *mut c_char) {
+    unsafe { free_cstring(s) }
+}
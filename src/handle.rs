@@ -1,20 +1,48 @@
-use crate::{Output, clip::Clip, event::Event};
-use anyhow::anyhow;
+use crate::{
+    Config, ConfigReadOption, ConnectionState, EventList, History, Output, Update,
+    clip::{Clip, Flavor},
+    command::{Callback, Command, OnDelivered},
+    error::{ClipTooLarge, ErrorCode, clear_last_error, set_last_error},
+    event::{Event, EventStream},
+    ffi::cstring_to_string,
+    input::FlavorInput,
+};
 use anyhow::{Context as _, Result};
-use std::{ffi::c_int, io::PipeReader, os::fd::AsRawFd, thread::JoinHandle};
+use anyhow::{anyhow, bail};
+use std::{
+    ffi::{c_int, c_void},
+    io::PipeReader,
+    os::fd::AsRawFd,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
 use tokio::sync::{
     mpsc::{UnboundedReceiver, UnboundedSender},
-    oneshot::{Receiver, Sender},
+    oneshot::Receiver,
 };
 use tokio_util::sync::CancellationToken;
 
 /// Representation of a "handle" for running MPClipboard
 pub struct Handle {
-    pub(crate) ctx: UnboundedSender<(Clip, Sender<bool>)>,
-    pub(crate) erx: UnboundedReceiver<Event>,
+    pub(crate) ctx: UnboundedSender<Command>,
+    pub(crate) erx: Option<UnboundedReceiver<Event>>,
     pub(crate) token: CancellationToken,
-    pub(crate) handle: JoinHandle<()>,
+    pub(crate) worker: Worker,
     pub(crate) pipe_reader: Option<PipeReader>,
+    pub(crate) supported: Vec<String>,
+    pub(crate) max_clip_size: Arc<AtomicUsize>,
+}
+
+// Whatever drives the main loop
+pub(crate) enum Worker {
+    // Dedicated thread, see `Thread::start`
+    Thread(std::thread::JoinHandle<()>),
+    // Task on the host's runtime, see `Client::spawn_on`
+    Task(tokio::task::JoinHandle<()>),
+    // Future that is driven by the host, see `Client::run`
+    External,
 }
 
 impl Handle {
@@ -22,7 +50,7 @@ impl Handle {
     /// this text and decides whether it's a duplicate or not. Doesn't wait for delivery.
     /// Returns `true` if given text is new (in such case it gets sent to the server).
     pub fn blocking_send(&self, text: &str) -> Result<bool> {
-        self.send_returning_rx(text)?
+        self.send_returning_rx(Clip::new(text), None)?
             .blocking_recv()
             .context("failed to recv reply: channel is closed")
     }
@@ -30,46 +58,289 @@ impl Handle {
     /// Sends text from local clipboard.
     /// Returns `true` if given text is new (in such case it gets sent to the server).
     pub async fn send(&self, text: &str) -> Result<bool> {
-        self.send_returning_rx(text)?
+        self.send_returning_rx(Clip::new(text), None)?
+            .await
+            .context("failed to recv reply: channel is closed")
+    }
+
+    /// Sends arbitrary content (e.g. `image/png`) from local clipboard, blocks until
+    /// background thread receives it and decides whether it's a duplicate or not.
+    /// Doesn't wait for delivery.
+    /// Returns `true` if given content is new (in such case it gets sent to the server).
+    pub fn blocking_send_bytes(&self, mime: &str, data: &[u8]) -> Result<bool> {
+        self.send_returning_rx(Clip::bytes(mime, data), None)?
+            .blocking_recv()
+            .context("failed to recv reply: channel is closed")
+    }
+
+    /// Sends arbitrary content (e.g. `image/png`) from local clipboard.
+    /// Returns `true` if given content is new (in such case it gets sent to the server).
+    pub async fn send_bytes(&self, mime: &str, data: &[u8]) -> Result<bool> {
+        self.send_returning_rx(Clip::bytes(mime, data), None)?
             .await
             .context("failed to recv reply: channel is closed")
     }
 
-    fn send_returning_rx(&self, text: &str) -> Result<Receiver<bool>> {
+    /// Sends several representations of the same copy (e.g. `text/plain` + `text/html`)
+    /// from local clipboard, blocks until background thread receives it and decides
+    /// whether it's a duplicate or not. Doesn't wait for delivery.
+    /// Returns `true` if given clip is new (in such case it gets sent to the server).
+    pub fn blocking_send_flavors(&self, flavors: Vec<Flavor>) -> Result<bool> {
+        if flavors.is_empty() {
+            bail!("clip must have at least one flavor");
+        }
+        self.send_returning_rx(Clip::with_flavors(flavors), None)?
+            .blocking_recv()
+            .context("failed to recv reply: channel is closed")
+    }
+
+    /// Sends several representations of the same copy (e.g. `text/plain` + `text/html`)
+    /// from local clipboard.
+    /// Returns `true` if given clip is new (in such case it gets sent to the server).
+    pub async fn send_flavors(&self, flavors: Vec<Flavor>) -> Result<bool> {
+        if flavors.is_empty() {
+            bail!("clip must have at least one flavor");
+        }
+        self.send_returning_rx(Clip::with_flavors(flavors), None)?
+            .await
+            .context("failed to recv reply: channel is closed")
+    }
+
+    /// Sends a clip from local clipboard and waits until the server confirms its delivery.
+    /// Returns `false` right away if given clip is a duplicate (in such case nothing is sent),
+    /// `true` once it's delivered and an error if the server has refused it or it has been
+    /// dropped from the outbound queue.
+    /// Servers that don't acknowledge clips can't confirm delivery,
+    /// with them a clip is considered delivered once it's written to the socket.
+    pub async fn send_and_wait_delivered(&self, clip: Clip) -> Result<bool> {
+        if clip.flavors.is_empty() {
+            bail!("clip must have at least one flavor");
+        }
+        let (tx, rx) = tokio::sync::oneshot::channel::<Result<()>>();
+        let on_delivered: OnDelivered = Box::new(move |result| {
+            let _ = tx.send(result);
+        });
+
+        let is_new = self
+            .send_returning_rx(clip, Some(on_delivered))?
+            .await
+            .context("failed to recv reply: channel is closed")?;
+        if !is_new {
+            return Ok(false);
+        }
+        rx.await
+            .context("failed to recv delivery status: channel is closed")??;
+        Ok(true)
+    }
+
+    fn send_returning_rx(
+        &self,
+        clip: Clip,
+        on_delivered: Option<OnDelivered>,
+    ) -> Result<Receiver<bool>> {
+        let max_clip_size = self.max_clip_size.load(Ordering::Relaxed);
+        if clip.size() > max_clip_size {
+            bail!(ClipTooLarge {
+                size: clip.size(),
+                max: max_clip_size,
+            });
+        }
         let (tx, rx) = tokio::sync::oneshot::channel::<bool>();
-        let clip = Clip::new(text);
-        self.ctx
-            .send((clip, tx))
-            .map_err(|_| anyhow!("failed to send command: channel is closed"))?;
+        self.command(Command::Send {
+            clip,
+            reply: tx,
+            on_delivered,
+        })?;
         Ok(rx)
     }
 
-    /// Polls background thread for any updates, squashes them and returns back to the caller.
-    /// Returns a pair of `new text received from the server` + `change of the connectivity`.
-    /// Both pair items can be empty (e.g. if there were no clips sent from the server)
-    pub fn recv(&mut self) -> (Option<String>, Option<bool>) {
-        let mut text = None;
-        let mut connectivity = None;
+    /// Returns local clipboard history (the most recent clip goes first),
+    /// blocks until background thread replies.
+    pub fn blocking_history(&self) -> Result<Vec<Clip>> {
+        self.history_returning_rx()?
+            .blocking_recv()
+            .context("failed to recv reply: channel is closed")
+    }
 
-        while let Ok(event) = self.erx.try_recv() {
+    /// Returns local clipboard history (the most recent clip goes first).
+    pub async fn history(&self) -> Result<Vec<Clip>> {
+        self.history_returning_rx()?
+            .await
+            .context("failed to recv reply: channel is closed")
+    }
+
+    fn history_returning_rx(&self) -> Result<Receiver<Vec<Clip>>> {
+        let (tx, rx) = tokio::sync::oneshot::channel::<Vec<Clip>>();
+        self.command(Command::History { reply: tx })?;
+        Ok(rx)
+    }
+
+    /// Cancels the pending reconnect delay (if any) and connects to the server right away,
+    /// useful when the host knows that the network is back (e.g. after waking up from sleep).
+    /// Doesn't block.
+    pub fn reconnect_now(&self) -> Result<()> {
+        self.command(Command::ReconnectNow)
+    }
+
+    /// Hints that the network has changed (e.g. Wi-Fi was switched to mobile data),
+    /// the current connection (that might be half-dead) is dropped and a new one
+    /// is made right away. Doesn't block.
+    pub fn notify_network_changed(&self) -> Result<()> {
+        self.command(Command::NetworkChanged)
+    }
+
+    /// Replaces the token that is used for authentication with the main server
+    /// (tokens of additional servers can be changed with `update_config`).
+    /// After an authentication failure the client stops reconnecting until
+    /// this method is called, then it connects right away. Doesn't block.
+    pub fn update_credentials(&self, token: &str) -> Result<()> {
+        self.command(Command::UpdateCredentials {
+            token: token.to_string(),
+        })
+    }
+
+    /// Applies a new config without restarting the background thread,
+    /// history and outbound queue are kept. Reconnects only if connection-related
+    /// fields (URI, token, name or compression) have changed. Doesn't block.
+    pub fn update_config(&self, config: Config) -> Result<()> {
+        self.command(Command::UpdateConfig {
+            config: Box::new(config),
+        })
+    }
+
+    /// Starts watching the config file (see `ConfigReadOption`),
+    /// every time it changes the config is reloaded and applied as in `update_config`.
+    /// Invalid configs are logged and ignored. Doesn't block.
+    /// Fails if the config file can't be located (e.g. `$HOME` is not set).
+    pub fn watch_config(&self, option: ConfigReadOption) -> Result<()> {
+        self.command(Command::WatchConfig {
+            path: option.path()?,
+        })
+    }
+
+    /// Registers a callback that is invoked every time there's a new event
+    /// (e.g. a clip from the server), so the host can `recv` it without polling.
+    /// Replaces previously registered callback. Doesn't block.
+    ///
+    /// The callback is invoked on the background thread, one call at a time,
+    /// after the event is available to `recv`. It must return quickly and must not
+    /// call methods that wait for the background thread (`blocking_send*`,
+    /// `blocking_history` and `stop`), these deadlock because the thread is busy
+    /// until the callback returns. `recv` and non-blocking methods are fine.
+    pub fn set_callback(&self, callback: impl Fn() + Send + 'static) -> Result<()> {
+        self.command(Command::SetCallback {
+            callback: Some(Box::new(callback)),
+        })
+    }
+
+    /// Unregisters a callback set with `set_callback`. Doesn't block.
+    pub fn remove_callback(&self) -> Result<()> {
+        self.command(Command::SetCallback { callback: None })
+    }
+
+    fn command(&self, command: Command) -> Result<()> {
+        self.ctx
+            .send(command)
+            .map_err(|_| anyhow!("failed to send command: channel is closed"))
+    }
+
+    /// Polls background thread for any updates, squashes them and returns back to the caller:
+    /// `new clip received from the server`, `change of the connectivity` (and its detailed status),
+    /// `latest latency`,
+    /// `latest progress of a large clip transfer` and local clips that have been
+    /// delivered (or failed to be delivered) since the last call.
+    /// All items can be empty (e.g. if there were no clips sent from the server)
+    pub fn recv(&mut self) -> Update {
+        let mut update = Update::default();
+        let Some(erx) = self.erx.as_mut() else {
+            return update;
+        };
+
+        while let Ok(event) = erx.try_recv() {
             match event {
-                Event::ConnectivityChanged(connected) => connectivity = Some(connected),
-                Event::NewClip(clip) => text = Some(clip.text),
+                Event::StatusChanged(status) => {
+                    match status.state {
+                        ConnectionState::Connected => update.connectivity = Some(true),
+                        ConnectionState::Disconnected | ConnectionState::AuthFailed => {
+                            update.connectivity = Some(false)
+                        }
+                        _ => {}
+                    }
+                    update.status = Some(status);
+                }
+                Event::ProfileStatusChanged(profile, status) => {
+                    update.profiles.insert(profile, status);
+                }
+                Event::NewClip(clip) => update.clip = Some(clip),
+                Event::Progress(progress) => update.progress = Some(progress),
+                Event::AuthFailed(reason) => update.auth_error = reason,
+                Event::Latency(latency) => update.latency = Some(latency),
+                Event::ClipDelivered(clip) => update.delivered.push(clip),
+                Event::ClipFailed(clip) => update.failed.push(clip),
+                Event::DecryptionFailed => update.decryption_failed = true,
+                Event::ClipRejected => update.clip_rejected = true,
+            }
+        }
+
+        update
+    }
+
+    /// Returns every event that has happened since the last call in order
+    /// (unlike `recv` nothing is squashed).
+    pub fn recv_all(&mut self) -> Vec<Event> {
+        let mut events = vec![];
+        if let Some(erx) = self.erx.as_mut() {
+            while let Ok(event) = erx.try_recv() {
+                events.push(event);
             }
         }
+        events
+    }
 
-        (text, connectivity)
+    /// Takes and returns a stream of every event in order (unlike `recv` nothing is squashed),
+    /// it can be used in async code as `while let Some(event) = events.next().await`.
+    /// Once the stream is taken `recv` and `recv_all` (and their C equivalents) return nothing,
+    /// so it must be called only once.
+    pub fn events(&mut self) -> Option<EventStream> {
+        self.erx.take().map(|erx| EventStream { erx })
     }
 
-    /// Gracefully shuts down a background thread
+    /// Sets MIME types that the host can put into its clipboard (ordered by preference),
+    /// it's used to pick the best flavor of received clips in `mpclipboard_handle_poll`
+    /// and `mpclipboard_handle_history`. Empty list (default) means "anything".
+    pub fn set_supported_mimes(&mut self, mimes: &[&str]) {
+        self.supported = mimes.iter().map(|mime| mime.to_string()).collect();
+    }
+
+    /// Gracefully shuts down a background thread.
+    /// Blocks until the thread exits if it's been started by `Thread::start`,
+    /// otherwise only signals the main loop to exit (use `shutdown` to wait for it
+    /// from async code).
     pub fn stop(self) -> Result<()> {
         self.token.cancel();
-        self.handle
-            .join()
-            .map_err(|_| anyhow!("failed to join thread (bug?)"))?;
+        if let Worker::Thread(handle) = self.worker {
+            handle
+                .join()
+                .map_err(|_| anyhow!("failed to join thread (bug?)"))?;
+        }
         Ok(())
     }
 
+    /// Gracefully shuts down the main loop and waits until it exits.
+    /// For a future returned by `Client::run` it only signals the main loop to exit,
+    /// the future completes right after that.
+    pub async fn shutdown(self) -> Result<()> {
+        self.token.cancel();
+        match self.worker {
+            Worker::Thread(handle) => tokio::task::spawn_blocking(move || handle.join())
+                .await
+                .context("failed to wait for thread")?
+                .map_err(|_| anyhow!("failed to join thread (bug?)")),
+            Worker::Task(handle) => handle.await.context("failed to join task (bug?)"),
+            Worker::External => Ok(()),
+        }
+    }
+
     /// Takes and returns a pipe reader that can be used to subscribe to updates
     /// in poll/epoll -like fashion.
     /// Every time there's an update this FD will get an update
@@ -85,6 +356,8 @@ impl Handle {
 /// Sends text from local clipboard, blocks until background thread receives
 /// this text and decides whether it's a duplicate or not. Doesn't wait for delivery.
 /// Returns `true` if given text is new (in such case it gets sent to the server).
+/// `false` is returned both for duplicates and failures, the latter can be told apart
+/// by `mpclipboard_last_error_code` returning anything but `None`.
 ///
 /// # Safety
 ///
@@ -95,25 +368,310 @@ pub unsafe extern "C" fn mpclipboard_handle_send(
     handle: *const Handle,
     text: *const std::ffi::c_char,
 ) -> bool {
+    clear_last_error();
     let handle = unsafe { &*handle };
 
     let Ok(text) = unsafe { std::ffi::CStr::from_ptr(text) }.to_str() else {
-        log::error!("text is not NULL-terminated");
+        set_last_error(
+            ErrorCode::InvalidArgument,
+            anyhow!("text is not a valid UTF-8 string"),
+        );
         return false;
     };
 
     match handle.blocking_send(text) {
         Ok(is_new) => is_new,
         Err(err) => {
+            set_last_error(ErrorCode::ThreadNotRunning, err);
+            false
+        }
+    }
+}
+
+/// Callback that is invoked once the server confirms delivery of a clip,
+/// receives `user_data` and `true` if the clip has been delivered (`false` otherwise)
+pub type DeliveryCallback = extern "C" fn(user_data: *mut c_void, delivered: bool);
+
+struct UserData(*mut c_void);
+// it's up to the caller to make `user_data` usable from another thread
+unsafe impl Send for UserData {}
+
+/// Sends text from local clipboard, blocks until background thread receives
+/// this text and decides whether it's a duplicate or not.
+/// Returns `true` if given text is new (in such case it gets sent to the server),
+/// then `callback` is invoked exactly once with the delivery status.
+/// `callback` is called on the background thread and must not block.
+///
+/// # Safety
+///
+/// `handle` must be a valid pointer to Handle
+/// `text` must be a NULL terminated C string
+/// `user_data` must be safe to use from another thread until `callback` is invoked
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mpclipboard_handle_send_with_callback(
+    handle: *const Handle,
+    text: *const std::ffi::c_char,
+    callback: DeliveryCallback,
+    user_data: *mut c_void,
+) -> bool {
+    clear_last_error();
+    let handle = unsafe { &*handle };
+
+    let Ok(text) = unsafe { std::ffi::CStr::from_ptr(text) }.to_str() else {
+        set_last_error(
+            ErrorCode::InvalidArgument,
+            anyhow!("text is not a valid UTF-8 string"),
+        );
+        return false;
+    };
+
+    send_with_callback(handle, Clip::new(text), callback, user_data)
+}
+
+fn send_with_callback(
+    handle: &Handle,
+    clip: Clip,
+    callback: DeliveryCallback,
+    user_data: *mut c_void,
+) -> bool {
+    let user_data = UserData(user_data);
+    let on_delivered: OnDelivered = Box::new(move |result| {
+        let user_data = user_data;
+        if let Err(err) = &result {
             log::error!("{err:?}");
+        }
+        callback(user_data.0, result.is_ok());
+    });
+
+    match handle
+        .send_returning_rx(clip, Some(on_delivered))
+        .and_then(|rx| {
+            rx.blocking_recv()
+                .context("failed to recv reply: channel is closed")
+        }) {
+        Ok(is_new) => is_new,
+        Err(err) => {
+            set_last_error(ErrorCode::ThreadNotRunning, err);
             false
         }
     }
 }
 
+/// Sends arbitrary content (e.g. `image/png`) from local clipboard, blocks until
+/// background thread receives it and decides whether it's a duplicate or not.
+/// Doesn't wait for delivery.
+/// Returns `true` if given content is new (in such case it gets sent to the server).
+///
+/// # Safety
+///
+/// `handle` must be a valid pointer to Handle
+/// `mime` must be a non-NULL, NULL terminated C string (NULL fails with `InvalidArgument`)
+/// `data` must be a valid pointer to `len` bytes (or NULL if `len` is 0)
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mpclipboard_handle_send_bytes(
+    handle: *const Handle,
+    mime: *const std::ffi::c_char,
+    data: *const u8,
+    len: usize,
+) -> bool {
+    clear_last_error();
+    let handle = unsafe { &*handle };
+
+    let (mime, data) = match unsafe { bytes_arg(mime, data, len) } {
+        Ok(pair) => pair,
+        Err(err) => {
+            set_last_error(ErrorCode::InvalidArgument, err);
+            return false;
+        }
+    };
+
+    match handle.blocking_send_bytes(mime, data) {
+        Ok(is_new) => is_new,
+        Err(err) => {
+            set_last_error(ErrorCode::ThreadNotRunning, err);
+            false
+        }
+    }
+}
+
+/// Sends several representations of the same copy (e.g. `text/plain` + `text/html`)
+/// from local clipboard, blocks until background thread receives it and decides
+/// whether it's a duplicate or not. Doesn't wait for delivery.
+/// Returns `true` if given clip is new (in such case it gets sent to the server).
+///
+/// # Safety
+///
+/// `handle` must be a valid pointer to Handle
+/// `flavors` must be a valid pointer to `len` flavors, every `mime` must be non-NULL
+/// (NULL fails with `InvalidArgument`)
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mpclipboard_handle_send_flavors(
+    handle: *const Handle,
+    flavors: *const FlavorInput,
+    len: usize,
+) -> bool {
+    clear_last_error();
+    let handle = unsafe { &*handle };
+
+    let flavors = match unsafe { flavors_arg(flavors, len) } {
+        Ok(flavors) => flavors,
+        Err(err) => {
+            set_last_error(ErrorCode::InvalidArgument, err);
+            return false;
+        }
+    };
+
+    match handle.blocking_send_flavors(flavors) {
+        Ok(is_new) => is_new,
+        Err(err) => {
+            set_last_error(ErrorCode::ThreadNotRunning, err);
+            false
+        }
+    }
+}
+
+/// Same as `mpclipboard_handle_send_bytes`, but `callback` is invoked exactly once
+/// with the delivery status if the content is new (see `mpclipboard_handle_send_with_callback`).
+///
+/// # Safety
+///
+/// `handle` must be a valid pointer to Handle
+/// `mime` must be a non-NULL, NULL terminated C string (NULL fails with `InvalidArgument`)
+/// `data` must be a valid pointer to `len` bytes (or NULL if `len` is 0)
+/// `user_data` must be safe to use from another thread until `callback` is invoked
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mpclipboard_handle_send_bytes_with_callback(
+    handle: *const Handle,
+    mime: *const std::ffi::c_char,
+    data: *const u8,
+    len: usize,
+    callback: DeliveryCallback,
+    user_data: *mut c_void,
+) -> bool {
+    clear_last_error();
+    let handle = unsafe { &*handle };
+
+    let (mime, data) = match unsafe { bytes_arg(mime, data, len) } {
+        Ok(pair) => pair,
+        Err(err) => {
+            set_last_error(ErrorCode::InvalidArgument, err);
+            return false;
+        }
+    };
+
+    send_with_callback(handle, Clip::bytes(mime, data), callback, user_data)
+}
+
+/// Same as `mpclipboard_handle_send_flavors`, but `callback` is invoked exactly once
+/// with the delivery status if the clip is new (see `mpclipboard_handle_send_with_callback`).
+///
+/// # Safety
+///
+/// `handle` must be a valid pointer to Handle
+/// `flavors` must be a valid pointer to `len` flavors, every `mime` must be non-NULL
+/// (NULL fails with `InvalidArgument`)
+/// `user_data` must be safe to use from another thread until `callback` is invoked
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mpclipboard_handle_send_flavors_with_callback(
+    handle: *const Handle,
+    flavors: *const FlavorInput,
+    len: usize,
+    callback: DeliveryCallback,
+    user_data: *mut c_void,
+) -> bool {
+    clear_last_error();
+    let handle = unsafe { &*handle };
+
+    let flavors = match unsafe { flavors_arg(flavors, len) } {
+        Ok(flavors) => flavors,
+        Err(err) => {
+            set_last_error(ErrorCode::InvalidArgument, err);
+            return false;
+        }
+    };
+
+    send_with_callback(handle, Clip::with_flavors(flavors), callback, user_data)
+}
+
+// `mime`, `data` and `len` arguments of `*_send_bytes*` functions
+unsafe fn bytes_arg<'a>(
+    mime: *const std::ffi::c_char,
+    data: *const u8,
+    len: usize,
+) -> Result<(&'a str, &'a [u8])> {
+    if mime.is_null() {
+        bail!("mime is NULL");
+    }
+    let mime = unsafe { std::ffi::CStr::from_ptr(mime) }
+        .to_str()
+        .context("mime is not a valid UTF-8 string")?;
+    let data = if len == 0 {
+        &[]
+    } else {
+        unsafe { std::slice::from_raw_parts(data, len) }
+    };
+    Ok((mime, data))
+}
+
+// `flavors` and `len` arguments of `*_send_flavors*` functions
+unsafe fn flavors_arg(flavors: *const FlavorInput, len: usize) -> Result<Vec<Flavor>> {
+    if flavors.is_null() || len == 0 {
+        bail!("clip must have at least one flavor");
+    }
+    unsafe { std::slice::from_raw_parts(flavors, len) }
+        .iter()
+        .map(|flavor| unsafe { flavor.to_flavor() })
+        .collect()
+}
+
+/// Sets MIME types that the host can put into its clipboard (ordered by preference),
+/// it's used to pick the best flavor of received clips in `mpclipboard_handle_poll`
+/// and `mpclipboard_handle_history`. Empty list (default) means "anything".
+///
+/// # Safety
+///
+/// `handle` must be a valid pointer to Handle
+/// `mimes` must be a valid pointer to `len` NULL terminated C strings (or NULL if `len` is 0)
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mpclipboard_handle_set_supported_mimes(
+    handle: *mut Handle,
+    mimes: *const *const std::ffi::c_char,
+    len: usize,
+) -> bool {
+    clear_last_error();
+    let handle = unsafe { &mut *handle };
+
+    let mimes = if len == 0 {
+        &[]
+    } else {
+        unsafe { std::slice::from_raw_parts(mimes, len) }
+    };
+    let mimes = match mimes
+        .iter()
+        .map(|mime| cstring_to_string(*mime))
+        .collect::<Result<Vec<_>>>()
+    {
+        Ok(mimes) => mimes,
+        Err(err) => {
+            set_last_error(ErrorCode::InvalidArgument, err);
+            return false;
+        }
+    };
+
+    handle.supported = mimes;
+    true
+}
+
 /// Polls background thread for any updates, squashes them and returns back to the caller.
-/// Returns a pair of `new text received from the server` + `change of the connectivity`.
-/// Both pair items can be empty (e.g. if there were no clips sent from the server)
+/// Returns `new clip received from the server`, `change of the connectivity`,
+/// `latest status of the connection` (with a delay before the next reconnect attempt),
+/// `latest latency`
+/// and `latest progress of a large clip transfer`.
+/// Out of all flavors of the clip the best supported one is returned
+/// (see `mpclipboard_handle_set_supported_mimes`): `text/plain` in `text`,
+/// anything else in `mime` + `data` + `len`.
+/// All items can be empty (e.g. if there were no clips sent from the server).
+/// Returned value must be released with `mpclipboard_output_free`.
 ///
 /// # Safety
 ///
@@ -121,8 +679,216 @@ pub unsafe extern "C" fn mpclipboard_handle_send(
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn mpclipboard_handle_poll(handle: *mut Handle) -> Output {
     let handle = unsafe { &mut *handle };
-    let (clip, connectivity) = handle.recv();
-    Output::new(clip, connectivity)
+    Output::new(handle.recv(), &handle.supported)
+}
+
+/// Polls background thread for every event that has happened since the last call
+/// in order (unlike `mpclipboard_handle_poll` nothing is squashed).
+/// Clips are represented by their best supported flavor
+/// (see `mpclipboard_handle_set_supported_mimes`), received clips that have
+/// no supported flavors are skipped.
+/// Returned value must be released with `mpclipboard_event_list_free`.
+///
+/// # Safety
+///
+/// `handle` must be a valid pointer to Handle
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mpclipboard_handle_poll_many(handle: *mut Handle) -> EventList {
+    let handle = unsafe { &mut *handle };
+    let events = handle.recv_all();
+    EventList::new(events, &handle.supported)
+}
+
+/// Returns local clipboard history (the most recent clip goes first),
+/// blocks until background thread replies.
+/// Returned value must be released with `mpclipboard_history_free`.
+///
+/// # Safety
+///
+/// `handle` must be a valid pointer to Handle
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mpclipboard_handle_history(handle: *const Handle) -> History {
+    clear_last_error();
+    let handle = unsafe { &*handle };
+    match handle.blocking_history() {
+        Ok(clips) => History::new(clips, &handle.supported),
+        Err(err) => {
+            set_last_error(ErrorCode::ThreadNotRunning, err);
+            History::null()
+        }
+    }
+}
+
+/// Cancels the pending reconnect delay (if any) and connects to the server right away,
+/// useful when the host knows that the network is back (e.g. after waking up from sleep).
+/// Doesn't block.
+///
+/// # Safety
+///
+/// `handle` must be a valid pointer to Handle
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mpclipboard_handle_reconnect_now(handle: *const Handle) -> bool {
+    clear_last_error();
+    let handle = unsafe { &*handle };
+    match handle.reconnect_now() {
+        Ok(()) => true,
+        Err(err) => {
+            set_last_error(ErrorCode::ThreadNotRunning, err);
+            false
+        }
+    }
+}
+
+/// Hints that the network has changed (e.g. Wi-Fi was switched to mobile data),
+/// the current connection (that might be half-dead) is dropped and a new one
+/// is made right away. Doesn't block.
+///
+/// # Safety
+///
+/// `handle` must be a valid pointer to Handle
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mpclipboard_handle_notify_network_changed(handle: *const Handle) -> bool {
+    clear_last_error();
+    let handle = unsafe { &*handle };
+    match handle.notify_network_changed() {
+        Ok(()) => true,
+        Err(err) => {
+            set_last_error(ErrorCode::ThreadNotRunning, err);
+            false
+        }
+    }
+}
+
+/// Replaces the token that is used for authentication with the main server
+/// (tokens of additional servers can be changed with `mpclipboard_handle_update_config`).
+/// After an authentication failure the client stops reconnecting until
+/// this function is called, then it connects right away. Doesn't block.
+///
+/// # Safety
+///
+/// `handle` must be a valid pointer to Handle
+/// `token` must be a NULL terminated C string
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mpclipboard_handle_update_credentials(
+    handle: *const Handle,
+    token: *const std::ffi::c_char,
+) -> bool {
+    clear_last_error();
+    let handle = unsafe { &*handle };
+
+    let token = match cstring_to_string(token) {
+        Ok(token) => token,
+        Err(err) => {
+            set_last_error(ErrorCode::InvalidArgument, err);
+            return false;
+        }
+    };
+
+    match handle.update_credentials(&token) {
+        Ok(()) => true,
+        Err(err) => {
+            set_last_error(ErrorCode::ThreadNotRunning, err);
+            false
+        }
+    }
+}
+
+/// Applies a new config without restarting the background thread,
+/// history and outbound queue are kept. Reconnects only if connection-related
+/// fields (URI, token, name or compression) have changed. Doesn't block.
+///
+/// # Safety
+///
+/// `handle` must be a valid pointer to Handle
+/// `config` must be a valid owned pointer to Config
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mpclipboard_handle_update_config(
+    handle: *const Handle,
+    config: *mut Config,
+) -> bool {
+    clear_last_error();
+    let handle = unsafe { &*handle };
+    let config = unsafe { Box::from_raw(config) };
+    match handle.update_config(*config) {
+        Ok(()) => true,
+        Err(err) => {
+            set_last_error(ErrorCode::ThreadNotRunning, err);
+            false
+        }
+    }
+}
+
+/// Starts watching the config file (see `mpclipboard_config_read_option_t`),
+/// every time it changes the config is reloaded and applied as in
+/// `mpclipboard_handle_update_config`. Invalid configs are logged and ignored.
+/// Doesn't block. Returns `false` if the config file can't be located
+/// (e.g. `$HOME` is not set).
+///
+/// # Safety
+///
+/// `handle` must be a valid pointer to Handle
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mpclipboard_handle_watch_config(
+    handle: *const Handle,
+    option: ConfigReadOption,
+) -> bool {
+    clear_last_error();
+    let handle = unsafe { &*handle };
+    match handle.watch_config(option) {
+        Ok(()) => true,
+        Err(err) => {
+            set_last_error(ErrorCode::ThreadNotRunning, err);
+            false
+        }
+    }
+}
+
+/// Callback that is invoked every time there's a new event to poll, receives `user_data`
+pub type EventCallback = Option<extern "C" fn(user_data: *mut c_void)>;
+
+/// Registers a callback that is invoked every time there's a new event
+/// (e.g. a clip from the server), so the host can `mpclipboard_handle_poll`
+/// without a busy loop. Replaces previously registered callback,
+/// NULL `callback` unregisters it. Doesn't block.
+///
+/// The callback is invoked on the background thread, one call at a time,
+/// after the event is available to `mpclipboard_handle_poll`. It must return quickly
+/// and must not call functions that wait for the background thread
+/// (`mpclipboard_handle_send*`, `mpclipboard_handle_history` and `mpclipboard_handle_stop`),
+/// these deadlock because the thread is busy until the callback returns.
+/// `mpclipboard_handle_poll`, `mpclipboard_handle_poll_many` and non-blocking functions
+/// are fine, though usually it's better to wake up the host's own event loop
+/// (e.g. `g_idle_add` or `dispatch_async` to the main queue).
+///
+/// # Safety
+///
+/// `handle` must be a valid pointer to Handle
+/// `user_data` must be safe to use from another thread until the callback is replaced
+/// or the handle is stopped
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mpclipboard_handle_set_callback(
+    handle: *const Handle,
+    callback: EventCallback,
+    user_data: *mut c_void,
+) -> bool {
+    clear_last_error();
+    let handle = unsafe { &*handle };
+
+    let callback = callback.map(|callback| -> Callback {
+        let user_data = UserData(user_data);
+        Box::new(move || {
+            let user_data = &user_data;
+            callback(user_data.0)
+        })
+    });
+
+    match handle.command(Command::SetCallback { callback }) {
+        Ok(()) => true,
+        Err(err) => {
+            set_last_error(ErrorCode::ThreadNotRunning, err);
+            false
+        }
+    }
 }
 
 /// Gracefully shuts down a background thread
@@ -132,11 +898,12 @@ pub unsafe extern "C" fn mpclipboard_handle_poll(handle: *mut Handle) -> Output
 /// `handle` must be a valid pointer to Handle
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn mpclipboard_handle_stop(handle: *mut Handle) -> bool {
+    clear_last_error();
     let handle = unsafe { Box::from_raw(handle) };
     match handle.stop() {
         Ok(()) => true,
         Err(err) => {
-            log::error!("failed to stop thread: {err:?}");
+            set_last_error(ErrorCode::ThreadStop, err.context("failed to stop thread"));
             false
         }
     }
@@ -0,0 +1,95 @@
+use crate::{
+    clip::Clip,
+    ffi::{flavor_to_ptrs, free_bytes, free_cstring},
+};
+use std::ffi::c_char;
+
+#[repr(C)]
+#[derive(Debug)]
+/// Single entry of the clipboard history
+pub struct HistoryItem {
+    /// Text of the clip, set only if the best supported flavor is `text/plain`
+    pub text: *mut c_char,
+    /// MIME type of the best supported non-text flavor
+    pub mime: *mut c_char,
+    /// Content of the best supported non-text flavor
+    pub data: *mut u8,
+    /// Length of `data`
+    pub len: usize,
+    /// Time when the clip was made (in milliseconds since UNIX epoch)
+    pub timestamp: u64,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+/// Owned array of history entries, the most recent one goes first.
+/// Clips that have no supported flavors are skipped.
+/// Must be released with `mpclipboard_history_free`.
+pub struct History {
+    /// Pointer to the first entry, NULL if history is empty
+    pub items: *mut HistoryItem,
+    /// Number of entries
+    pub len: usize,
+}
+
+impl History {
+    pub(crate) fn null() -> Self {
+        Self {
+            items: std::ptr::null_mut(),
+            len: 0,
+        }
+    }
+
+    pub(crate) fn new(clips: Vec<Clip>, supported: &[String]) -> Self {
+        let items = clips
+            .into_iter()
+            .filter_map(|clip| {
+                let flavor = flavor_to_ptrs(clip.best_flavor(supported)?.clone());
+                Some(HistoryItem {
+                    text: flavor.text,
+                    mime: flavor.mime,
+                    data: flavor.data,
+                    len: flavor.len,
+                    timestamp: u64::try_from(clip.timestamp).unwrap_or(u64::MAX),
+                })
+            })
+            .collect::<Box<[_]>>();
+        if items.is_empty() {
+            return Self::null();
+        }
+
+        let len = items.len();
+
+        Self {
+            items: Box::leak(items).as_mut_ptr(),
+            len,
+        }
+    }
+}
+
+/// Releases history returned by `mpclipboard_handle_history`
+///
+/// # Safety
+///
+/// `history` must be a value returned by `mpclipboard_handle_history`
+/// that hasn't been released yet
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mpclipboard_history_free(history: History) {
+    if history.items.is_null() {
+        return;
+    }
+
+    let items = unsafe {
+        Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+            history.items,
+            history.len,
+        ))
+    };
+    for item in items {
+        unsafe {
+            free_cstring(item.text);
+            free_cstring(item.mime);
+            free_bytes(item.data, item.len);
+        }
+    }
+}
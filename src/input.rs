@@ -0,0 +1,33 @@
+use crate::clip::Flavor;
+use anyhow::{Context as _, Result, bail};
+use std::ffi::c_char;
+
+#[repr(C)]
+#[derive(Debug)]
+/// Single representation of a clip that is sent to the server
+pub struct FlavorInput {
+    /// MIME type of the content (e.g. `"text/plain"` or `"text/html"`), NULL terminated,
+    /// must not be NULL
+    pub mime: *const c_char,
+    /// Raw content (can be NULL if `len` is 0)
+    pub data: *const u8,
+    /// Length of `data`
+    pub len: usize,
+}
+
+impl FlavorInput {
+    pub(crate) unsafe fn to_flavor(&self) -> Result<Flavor> {
+        if self.mime.is_null() {
+            bail!("mime is NULL");
+        }
+        let mime = unsafe { std::ffi::CStr::from_ptr(self.mime) }
+            .to_str()
+            .context("mime is not a valid UTF-8 string")?;
+        let data = if self.len == 0 {
+            &[]
+        } else {
+            unsafe { std::slice::from_raw_parts(self.data, self.len) }
+        };
+        Ok(Flavor::new(mime, data))
+    }
+}
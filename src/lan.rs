@@ -0,0 +1,523 @@
+use crate::{
+    LanConfig,
+    clip::Clip,
+    crypto::{Cipher, Signer},
+};
+use anyhow::{Context as _, Result, bail};
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    collections::{HashMap, HashSet},
+    net::{Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::{
+        Semaphore,
+        mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel},
+    },
+    time::{Instant, sleep_until, timeout},
+};
+
+// Peers announce themselves with UDP beacons and push clips to each other
+// over short-lived TCP connections, one clip per connection.
+// Beacon layout:
+// [HMAC tag][JSON beacon]
+// Frame layout:
+// [u32 BE: length][encrypted: [u64 BE: sender id][u64 BE: counter][u64 BE: timestamp][binary clip]]
+// Only peers that know the passphrase can produce valid beacons and frames.
+// Timestamps (compared with the local clock) and seen (sender, counter) pairs
+// make captured beacons and frames useless once they are replayed.
+
+const APP: &str = "mpclipboard-lan-v1";
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+// binary clip header + encryption envelope on top of the clip size
+const FRAME_OVERHEAD: usize = 64 * 1024;
+// connections over the limit are dropped right away,
+// so a flood of them can't exhaust memory
+const MAX_INBOUND: usize = 8;
+const MAX_PEERS: usize = 64;
+// older (or newer) beacons and frames are dropped
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+const FRAME_HEADER_LEN: usize = 8 + 8 + 8;
+// the beacon socket is polled less and less often while it keeps failing
+const MAX_UDP_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+// (sender id, counter) of received frames along with their timestamps,
+// outlives `Lan` so frames can't be replayed after a restart
+pub(crate) type SeenFrames = Arc<Mutex<HashMap<(u64, u64), u64>>>;
+
+#[derive(Serialize, Deserialize)]
+struct Beacon {
+    app: String,
+    id: u64,
+    name: String,
+    port: u16,
+    timestamp: u64,
+}
+
+struct Peer {
+    name: String,
+    addr: SocketAddr,
+    last_seen: Instant,
+    // replayed beacons (that are older than the latest one) are ignored
+    last_beacon: u64,
+}
+
+pub(crate) enum LanEvent {
+    ReceivedClip(Clip),
+    DecryptionFailed,
+    Ignored,
+}
+
+pub(crate) struct Lan {
+    id: u64,
+    name: String,
+    config: LanConfig,
+    passphrase: String,
+    cipher: Arc<Cipher>,
+    signer: Signer,
+    socket: UdpSocket,
+    listener: TcpListener,
+    port: u16,
+    peers: HashMap<u64, Peer>,
+    // peers with a different passphrase that have been reported already
+    mismatched: HashSet<u64>,
+    next_beacon_at: Instant,
+    counter: u64,
+    seen: SeenFrames,
+    udp_errors: u32,
+    udp_retry_at: Instant,
+    max_clip_size: usize,
+    // frames read by per-connection tasks
+    inbound: Arc<Semaphore>,
+    ftx: UnboundedSender<Vec<u8>>,
+    frx: UnboundedReceiver<Vec<u8>>,
+}
+
+impl Lan {
+    // Must be called within Tokio runtime
+    pub(crate) fn new(
+        config: &LanConfig,
+        name: &str,
+        passphrase: Option<&str>,
+        max_clip_size: usize,
+        seen: SeenFrames,
+    ) -> Result<Self> {
+        let Some(passphrase) = passphrase else {
+            bail!("LAN sync requires a passphrase");
+        };
+
+        let socket = bind_udp(config.port)?;
+        let listener = std::net::TcpListener::bind((Ipv4Addr::UNSPECIFIED, 0))
+            .context("failed to bind TCP listener")?;
+        listener.set_nonblocking(true)?;
+        let listener = TcpListener::from_std(listener)?;
+        let port = listener.local_addr()?.port();
+        let (ftx, frx) = unbounded_channel();
+        log::info!(
+            "[lan] listening for beacons on {}, clips on {port}",
+            config.port
+        );
+
+        Ok(Self {
+            id: fastrand::u64(..),
+            name: name.to_string(),
+            config: config.clone(),
+            passphrase: passphrase.to_string(),
+            cipher: Arc::new(Cipher::new(passphrase)),
+            signer: Signer::new(passphrase),
+            socket,
+            listener,
+            port,
+            peers: HashMap::new(),
+            mismatched: HashSet::new(),
+            next_beacon_at: Instant::now(),
+            counter: 0,
+            seen,
+            udp_errors: 0,
+            udp_retry_at: Instant::now(),
+            max_clip_size,
+            inbound: Arc::new(Semaphore::new(MAX_INBOUND)),
+            ftx,
+            frx,
+        })
+    }
+
+    // Returns `true` if the new settings can't be applied without re-binding sockets
+    pub(crate) fn needs_restart(&self, config: &LanConfig, passphrase: Option<&str>) -> bool {
+        self.config != *config || Some(self.passphrase.as_str()) != passphrase
+    }
+
+    pub(crate) fn set_max_clip_size(&mut self, max_clip_size: usize) {
+        self.max_clip_size = max_clip_size;
+    }
+
+    // Cancel-safe
+    pub(crate) async fn recv(&mut self) -> LanEvent {
+        let mut buf = [0; 2048];
+        let retry_at = self.udp_retry_at;
+        tokio::select! {
+            received = async {
+                sleep_until(retry_at).await;
+                self.socket.recv_from(&mut buf).await
+            } => match received {
+                Ok((len, from)) => {
+                    self.udp_errors = 0;
+                    self.beacon(&buf[..len], from)
+                }
+                Err(err) => {
+                    self.udp_errors += 1;
+                    let delay = (Duration::from_millis(100) * 2_u32.pow(self.udp_errors.min(10)))
+                        .min(MAX_UDP_RETRY_DELAY);
+                    log::error!("[lan] failed to receive beacon, retrying in {delay:?}: {err:?}");
+                    self.udp_retry_at = Instant::now() + delay;
+                    LanEvent::Ignored
+                }
+            },
+
+            accepted = self.listener.accept() => {
+                match accepted {
+                    Ok((stream, from)) => {
+                        let Ok(permit) = Arc::clone(&self.inbound).try_acquire_owned() else {
+                            log::warn!("[lan] too many inbound transfers, dropping {from}");
+                            return LanEvent::Ignored;
+                        };
+                        let ftx = self.ftx.clone();
+                        let max_len = self.max_clip_size + FRAME_OVERHEAD;
+                        tokio::spawn(async move {
+                            let _permit = permit;
+                            match timeout(IO_TIMEOUT, read_frame(stream, max_len)).await {
+                                Ok(Ok(frame)) => {
+                                    let _ = ftx.send(frame);
+                                }
+                                Ok(Err(err)) => log::warn!("[lan] bad frame from {from}: {err:?}"),
+                                Err(_) => log::warn!("[lan] {from} is too slow, dropping"),
+                            }
+                        });
+                    }
+                    Err(err) => log::error!("[lan] failed to accept connection: {err:?}"),
+                }
+                LanEvent::Ignored
+            },
+
+            Some(frame) = self.frx.recv() => self.frame(&frame),
+        }
+    }
+
+    fn beacon(&mut self, bytes: &[u8], from: SocketAddr) -> LanEvent {
+        let Some(beacon) = bytes
+            .get(Signer::TAG_LEN..)
+            .and_then(|json| serde_json::from_slice::<Beacon>(json).ok())
+        else {
+            log::trace!("[lan] ignoring unknown datagram from {from}");
+            return LanEvent::Ignored;
+        };
+        if beacon.app != APP || beacon.id == self.id {
+            return LanEvent::Ignored;
+        }
+        if self.signer.verify(bytes).is_none() {
+            // either a peer with a different passphrase or a forged beacon,
+            // reported once per peer
+            if self.mismatched.len() < MAX_PEERS && self.mismatched.insert(beacon.id) {
+                log::warn!("[lan] beacon of {} can't be verified", beacon.name);
+                return LanEvent::DecryptionFailed;
+            }
+            return LanEvent::Ignored;
+        }
+        if !is_fresh(beacon.timestamp) {
+            log::warn!(
+                "[lan] stale beacon from {}, is the clock in sync?",
+                beacon.name
+            );
+            return LanEvent::Ignored;
+        }
+
+        let addr = SocketAddr::new(from.ip(), beacon.port);
+        let is_full = self.peers.len() >= MAX_PEERS;
+        match self.peers.get_mut(&beacon.id) {
+            Some(peer) if beacon.timestamp <= peer.last_beacon => {
+                log::trace!("[lan] ignoring replayed beacon of {}", peer.name);
+            }
+            Some(peer) => {
+                peer.addr = addr;
+                peer.last_seen = Instant::now();
+                peer.last_beacon = beacon.timestamp;
+            }
+            None if is_full => {
+                log::warn!("[lan] too many peers, ignoring {}", beacon.name);
+            }
+            None => {
+                log::info!("[lan] found peer {} at {addr}", beacon.name);
+                let peer = Peer {
+                    name: beacon.name,
+                    addr,
+                    last_seen: Instant::now(),
+                    last_beacon: beacon.timestamp,
+                };
+                self.peers.insert(beacon.id, peer);
+            }
+        }
+        LanEvent::Ignored
+    }
+
+    fn frame(&mut self, frame: &[u8]) -> LanEvent {
+        let Ok(payload) = self.cipher.decrypt(frame) else {
+            log::warn!("[lan] received clip can't be decrypted");
+            return LanEvent::DecryptionFailed;
+        };
+        let Some((header, clip)) = payload.split_first_chunk::<FRAME_HEADER_LEN>() else {
+            log::warn!("[lan] received frame is too short");
+            return LanEvent::Ignored;
+        };
+        let sender = u64::from_be_bytes(header[0..8].try_into().expect("8 bytes"));
+        let counter = u64::from_be_bytes(header[8..16].try_into().expect("8 bytes"));
+        let timestamp = u64::from_be_bytes(header[16..24].try_into().expect("8 bytes"));
+        if !is_fresh(timestamp) {
+            log::warn!("[lan] dropping stale clip (replayed or the clock is out of sync)");
+            return LanEvent::Ignored;
+        }
+        let mut seen = self.seen.lock().expect("seen frames lock is poisoned");
+        if seen.insert((sender, counter), timestamp).is_some() {
+            log::warn!("[lan] dropping replayed clip");
+            return LanEvent::Ignored;
+        }
+
+        match Clip::from_binary(clip) {
+            Ok(clip) if clip.size() <= self.max_clip_size => LanEvent::ReceivedClip(clip),
+            Ok(clip) => {
+                log::warn!("[lan] received clip is too large ({} bytes)", clip.size());
+                LanEvent::Ignored
+            }
+            Err(err) => {
+                log::warn!("[lan] malformed clip: {err:?}");
+                LanEvent::Ignored
+            }
+        }
+    }
+
+    // Sends beacons and forgets peers that have gone silent
+    pub(crate) fn tick(&mut self) {
+        let now = Instant::now();
+        let interval = Duration::from_secs(self.config.beacon_interval_secs.max(1));
+        self.peers.retain(|_, peer| {
+            let alive = now.duration_since(peer.last_seen) < interval * 3;
+            if !alive {
+                log::info!("[lan] peer {} is gone", peer.name);
+            }
+            alive
+        });
+        // older frames are rejected as stale anyway
+        self.seen
+            .lock()
+            .expect("seen frames lock is poisoned")
+            .retain(|_, timestamp| is_fresh(*timestamp));
+
+        if self.next_beacon_at > now {
+            return;
+        }
+        self.next_beacon_at = now + interval;
+
+        let beacon = serde_json::to_vec(&Beacon {
+            app: APP.to_string(),
+            id: self.id,
+            name: self.name.clone(),
+            port: self.port,
+            timestamp: now_ms(),
+        })
+        .expect("failed to serialize beacon");
+        let beacon = self.signer.sign(&beacon);
+
+        let mut targets = self.config.peers.clone();
+        if self.config.broadcast {
+            targets.push(SocketAddr::from((Ipv4Addr::BROADCAST, self.config.port)));
+        }
+        for target in targets {
+            if let Err(err) = self.socket.try_send_to(&beacon, target) {
+                log::trace!("[lan] failed to send beacon to {target}: {err:?}");
+            }
+        }
+    }
+
+    // Best effort, there's no delivery confirmation
+    pub(crate) fn send(&mut self, clip: &Clip) {
+        if self.peers.is_empty() {
+            return;
+        }
+        self.counter += 1;
+        let frame = match self.seal(self.counter, now_ms(), clip) {
+            Ok(frame) => Arc::new(frame),
+            Err(err) => {
+                log::error!("[lan] failed to encrypt clip: {err:?}");
+                return;
+            }
+        };
+
+        for peer in self.peers.values() {
+            let frame = Arc::clone(&frame);
+            let addr = peer.addr;
+            let name = peer.name.clone();
+            tokio::spawn(async move {
+                match timeout(IO_TIMEOUT, write_frame(addr, &frame)).await {
+                    Ok(Ok(())) => log::info!("[lan] clip has been sent to {name}"),
+                    Ok(Err(err)) => log::warn!("[lan] failed to send clip to {name}: {err:?}"),
+                    Err(_) => log::warn!("[lan] {name} is too slow, dropping"),
+                }
+            });
+        }
+    }
+
+    fn seal(&self, counter: u64, timestamp: u64, clip: &Clip) -> Result<Vec<u8>> {
+        let mut payload = Vec::with_capacity(FRAME_HEADER_LEN + clip.size());
+        payload.extend_from_slice(&self.id.to_be_bytes());
+        payload.extend_from_slice(&counter.to_be_bytes());
+        payload.extend_from_slice(&timestamp.to_be_bytes());
+        payload.extend_from_slice(&clip.to_binary());
+        self.cipher.encrypt(&payload)
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis() as u64
+}
+
+fn is_fresh(timestamp: u64) -> bool {
+    now_ms().abs_diff(timestamp) <= MAX_CLOCK_SKEW.as_millis() as u64
+}
+
+// Several clients on the same machine can share the beacon port
+fn bind_udp(port: u16) -> Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))
+        .context("failed to create UDP socket")?;
+    socket.set_reuse_address(true)?;
+    socket.set_broadcast(true)?;
+    socket.set_nonblocking(true)?;
+    socket
+        .bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)).into())
+        .with_context(|| format!("failed to bind UDP port {port}"))?;
+    Ok(UdpSocket::from_std(socket.into())?)
+}
+
+// Memory grows with the data that has actually arrived, not with the declared length
+async fn read_frame(mut stream: TcpStream, max_len: usize) -> Result<Vec<u8>> {
+    let len = stream.read_u32().await? as usize;
+    if len > max_len {
+        bail!("frame is too large ({len} bytes)");
+    }
+    let mut frame = vec![];
+    stream.take(len as u64).read_to_end(&mut frame).await?;
+    if frame.len() != len {
+        bail!("frame is truncated ({} of {len} bytes)", frame.len());
+    }
+    Ok(frame)
+}
+
+async fn write_frame(addr: SocketAddr, frame: &[u8]) -> Result<()> {
+    let mut stream = TcpStream::connect(addr).await?;
+    stream.write_u32(frame.len() as u32).await?;
+    stream.write_all(frame).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lan(passphrase: &str, seen: &SeenFrames) -> Lan {
+        let config = LanConfig {
+            enabled: true,
+            port: 0,
+            broadcast: false,
+            ..LanConfig::default()
+        };
+        Lan::new(&config, "test", Some(passphrase), 1024, Arc::clone(seen)).unwrap()
+    }
+
+    fn beacon(lan: &Lan, timestamp: u64) -> Vec<u8> {
+        let beacon = serde_json::to_vec(&Beacon {
+            app: APP.to_string(),
+            id: lan.id,
+            name: lan.name.clone(),
+            port: lan.port,
+            timestamp,
+        })
+        .unwrap();
+        lan.signer.sign(&beacon)
+    }
+
+    fn from() -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], 1))
+    }
+
+    #[tokio::test]
+    async fn beacon_with_bad_signature_is_rejected() {
+        let mut receiver = lan("secret", &SeenFrames::default());
+        let stranger = lan("another secret", &SeenFrames::default());
+        let event = receiver.beacon(&beacon(&stranger, now_ms()), from());
+        assert!(matches!(event, LanEvent::DecryptionFailed));
+
+        // a peer with the right passphrase can't be impersonated by editing its beacon
+        let peer = lan("secret", &SeenFrames::default());
+        let mut forged = beacon(&peer, now_ms());
+        let port = peer.port.to_string();
+        let at = forged
+            .windows(port.len())
+            .rposition(|window| window == port.as_bytes())
+            .unwrap();
+        forged[at] = if forged[at] == b'1' { b'2' } else { b'1' };
+        assert!(matches!(
+            receiver.beacon(&forged, from()),
+            LanEvent::DecryptionFailed
+        ));
+        assert!(receiver.peers.is_empty());
+
+        receiver.beacon(&beacon(&peer, now_ms()), from());
+        assert!(receiver.peers.contains_key(&peer.id));
+    }
+
+    #[tokio::test]
+    async fn stale_beacons_and_frames_are_dropped() {
+        let mut receiver = lan("secret", &SeenFrames::default());
+        let sender = lan("secret", &SeenFrames::default());
+        let stale = now_ms() - 2 * MAX_CLOCK_SKEW.as_millis() as u64;
+
+        receiver.beacon(&beacon(&sender, stale), from());
+        assert!(receiver.peers.is_empty());
+
+        let frame = sender.seal(1, stale, &Clip::new("stale")).unwrap();
+        assert!(matches!(receiver.frame(&frame), LanEvent::Ignored));
+    }
+
+    #[tokio::test]
+    async fn replayed_frames_are_dropped() {
+        let seen = SeenFrames::default();
+        let mut receiver = lan("secret", &seen);
+        let sender = lan("secret", &SeenFrames::default());
+
+        let frame = sender.seal(1, now_ms(), &Clip::new("hello")).unwrap();
+        assert!(matches!(
+            receiver.frame(&frame),
+            LanEvent::ReceivedClip(clip) if clip.as_text() == Some("hello")
+        ));
+        assert!(matches!(receiver.frame(&frame), LanEvent::Ignored));
+
+        // the same counter with a different payload is a replay too
+        let frame = sender.seal(1, now_ms(), &Clip::new("other")).unwrap();
+        assert!(matches!(receiver.frame(&frame), LanEvent::Ignored));
+
+        // restarting LAN (e.g. on config reload) doesn't forget received frames
+        drop(receiver);
+        let mut receiver = lan("secret", &seen);
+        let frame = sender.seal(1, now_ms(), &Clip::new("hello")).unwrap();
+        assert!(matches!(receiver.frame(&frame), LanEvent::Ignored));
+        let frame = sender.seal(2, now_ms(), &Clip::new("next")).unwrap();
+        assert!(matches!(receiver.frame(&frame), LanEvent::ReceivedClip(_)));
+    }
+}
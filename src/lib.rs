@@ -6,28 +6,70 @@
 #![allow(clippy::boxed_local)]
 #![doc = include_str!("../README.md")]
 
-pub use config::{Config, ConfigReadOption, mpclipboard_config_new, mpclipboard_config_read};
+pub use client::Client;
+pub use clip::{Clip, Flavor, TEXT_PLAIN};
+pub use config::{
+    Config, ConfigReadOption, DEFAULT_PROFILE, HeartbeatConfig, HistoryConfig, LanConfig,
+    QueueConfig, QueuePolicy, ReconnectConfig, RoutingPolicy, ServerConfig, TransferConfig,
+    mpclipboard_config_free, mpclipboard_config_new, mpclipboard_config_read,
+    mpclipboard_config_set_passphrase,
+};
+pub use error::{ErrorCode, mpclipboard_last_error_code, mpclipboard_last_error_message};
+pub use event::{Event, EventStream, Update};
+pub use event_list::{EventItem, EventKind, EventList, mpclipboard_event_list_free};
+pub use ffi::mpclipboard_string_free;
 pub use handle::{
-    Handle, mpclipboard_handle_poll, mpclipboard_handle_send, mpclipboard_handle_stop,
-    mpclipboard_handle_take_fd,
+    DeliveryCallback, EventCallback, Handle, mpclipboard_handle_history,
+    mpclipboard_handle_notify_network_changed, mpclipboard_handle_poll,
+    mpclipboard_handle_poll_many, mpclipboard_handle_reconnect_now, mpclipboard_handle_send,
+    mpclipboard_handle_send_bytes, mpclipboard_handle_send_bytes_with_callback,
+    mpclipboard_handle_send_flavors, mpclipboard_handle_send_flavors_with_callback,
+    mpclipboard_handle_send_with_callback, mpclipboard_handle_set_callback,
+    mpclipboard_handle_set_supported_mimes, mpclipboard_handle_stop, mpclipboard_handle_take_fd,
+    mpclipboard_handle_update_config, mpclipboard_handle_update_credentials,
+    mpclipboard_handle_watch_config,
 };
+pub use history::{History, HistoryItem, mpclipboard_history_free};
+pub use input::FlavorInput;
 pub use logger::{Logger, mpclipboard_logger_test};
-pub use output::Output;
+pub use output::{Output, mpclipboard_output_free};
+pub use progress::{Direction, Progress};
+#[cfg(feature = "server")]
+pub use server::{Relay, RelayConfig};
+pub use status::{ConnectionState, ConnectionStatus};
 pub use thread::{Thread, mpclipboard_thread_start};
 pub use tls::TLS;
 
+mod backoff;
+mod chunks;
+mod client;
 mod clip;
+mod command;
+mod compression;
 mod config;
 mod connection;
+mod crypto;
+mod disk;
+mod error;
 mod event;
+mod event_list;
 mod ffi;
 mod handle;
+mod history;
+mod input;
+mod lan;
 mod logger;
 mod main_loop;
 mod output;
+mod progress;
+mod queue;
+#[cfg(feature = "server")]
+mod server;
+mod status;
 mod store;
 mod thread;
 mod tls;
+mod watcher;
 
 /// Initializes MPClipboard's Logger and TLS connector.
 ///
@@ -37,10 +79,11 @@ mod tls;
 /// Returns `false` if TLS connector can't be initialized.
 #[unsafe(no_mangle)]
 pub extern "C" fn mpclipboard_init() -> bool {
+    error::clear_last_error();
     Logger::init();
 
     if let Err(err) = TLS::init() {
-        log::error!("failed to init WS connector: {err:?}");
+        error::set_last_error(ErrorCode::Tls, err.context("failed to init WS connector"));
         return false;
     }
     log::info!("TLS Connector has been configured");
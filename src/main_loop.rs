@@ -1,11 +1,23 @@
 use crate::{
-    Config,
+    Config, ConnectionState, ConnectionStatus, HeartbeatConfig, RoutingPolicy,
+    command::{Callback, Command, OnDelivered},
     connection::{Connection, ConnectionEvent},
     event::Event,
+    lan::{Lan, LanEvent, SeenFrames},
+    progress::Direction,
+    queue::Outgoing,
+    watcher::ConfigWatcher,
 };
 use crate::{clip::Clip, store::Store};
+use anyhow::anyhow;
+use futures::future::select_all;
 use std::{
+    collections::HashMap,
     io::{PipeWriter, Write as _},
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
     time::Duration,
 };
 use tokio::{
@@ -18,35 +30,92 @@ use tokio::{
 use tokio_util::sync::CancellationToken;
 
 pub(crate) struct MainLoop {
-    crx: UnboundedReceiver<(Clip, Sender<bool>)>,
+    crx: UnboundedReceiver<Command>,
     etx: UnboundedSender<Event>,
     token: CancellationToken,
     store: Store,
-    conn: Connection,
+    // one per server profile, the default one goes first
+    servers: Vec<Server>,
+    routing: RoutingPolicy,
+    lan: Option<Lan>,
+    // kept across LAN restarts, so frames can't be replayed after `update_config`
+    lan_seen: SeenFrames,
     pipe_writer: PipeWriter,
+    deliveries: HashMap<u64, Delivery>,
+    // shared with `Handle` that rejects large clips before sending them here
+    max_clip_size: Arc<AtomicUsize>,
+    watcher: Option<ConfigWatcher>,
+    callback: Option<Callback>,
 
+    heartbeat: HeartbeatConfig,
     timer: Interval,
+}
+
+struct Server {
+    profile: String,
+    conn: Connection,
+    send: bool,
+    status: ConnectionStatus,
     reconnect_at: Instant,
+    next_ping_at: Instant,
+}
+
+impl Server {
+    fn new(profile: String, config: Config, send: bool, heartbeat: &HeartbeatConfig) -> Self {
+        Self {
+            conn: Connection::new(&profile, config),
+            profile,
+            send,
+            status: ConnectionStatus::new(ConnectionState::Connecting, None),
+            reconnect_at: liveness_deadline(heartbeat),
+            next_ping_at: Instant::now(),
+        }
+    }
+}
+
+// Local clip that is sent to one or more servers, it's delivered once
+// any server confirms it and failed once all of them give up
+struct Delivery {
+    on_delivered: Option<OnDelivered>,
+    pending: usize,
+    delivered: bool,
 }
 
 impl MainLoop {
     pub(crate) fn new(
-        crx: UnboundedReceiver<(Clip, Sender<bool>)>,
+        crx: UnboundedReceiver<Command>,
         etx: UnboundedSender<Event>,
         config: Config,
         token: CancellationToken,
         pipe_writer: PipeWriter,
+        max_clip_size: Arc<AtomicUsize>,
     ) -> Self {
+        let heartbeat = config.heartbeat.clone();
+        let timer = tick_interval(&heartbeat);
+        let lan_seen = SeenFrames::default();
+        let servers = config
+            .profiles()
+            .into_iter()
+            .map(|profile| Server::new(profile.name, profile.config, profile.send, &heartbeat))
+            .collect();
+
         Self {
             crx,
             etx,
             token,
-            store: Store::new(),
-            conn: Connection::new(config),
+            store: Store::new(&config.history),
+            servers,
+            routing: config.routing,
+            lan: start_lan(&config, &lan_seen),
+            lan_seen,
             pipe_writer,
+            deliveries: HashMap::new(),
+            max_clip_size,
+            watcher: None,
+            callback: None,
 
-            timer: interval(Duration::from_secs(1)),
-            reconnect_at: fifteen_secs_from_now(),
+            heartbeat,
+            timer,
         }
     }
 
@@ -58,12 +127,16 @@ impl MainLoop {
                     break;
                 },
 
-                Some((clip_to_send, reply)) = self.crx.recv() => {
-                    self.send_clip(clip_to_send, reply).await;
+                Some(command) = self.crx.recv() => {
+                    self.process_command(command).await;
+                }
+
+                (idx, event) = next_event(&mut self.servers) => {
+                    self.process_event(idx, event).await;
                 }
 
-                event = self.conn.recv() => {
-                    self.process_event(event).await;
+                event = next_lan_event(&mut self.lan) => {
+                    self.process_lan_event(event).await;
                 }
 
                 _ = self.timer.tick() => {
@@ -71,17 +144,199 @@ impl MainLoop {
                 }
             }
         }
+
+        // every callback must be invoked exactly once, even if the clip is still in flight
+        for (_, delivery) in self.deliveries.drain() {
+            if let Some(on_delivered) = delivery.on_delivered {
+                on_delivered(Err(anyhow!(
+                    "client has been stopped before the clip was delivered"
+                )));
+            }
+        }
     }
 
-    async fn send_clip(&mut self, clip: Clip, reply: Sender<bool>) {
+    async fn process_command(&mut self, command: Command) {
+        match command {
+            Command::Send {
+                clip,
+                reply,
+                on_delivered,
+            } => self.send_clip(clip, reply, on_delivered).await,
+            Command::History { reply } => {
+                if reply.send(self.store.history()).is_err() {
+                    log::error!("failed to send reply back: channel is closed");
+                }
+            }
+            Command::ReconnectNow => self.reconnect_now().await,
+            Command::NetworkChanged => self.network_changed().await,
+            Command::UpdateConfig { config } => self.update_config(*config).await,
+            Command::WatchConfig { path } => self.watcher = Some(ConfigWatcher::new(path)),
+            Command::SetCallback { callback } => self.callback = callback,
+            Command::UpdateCredentials { token } => {
+                log::info!("credentials have been updated");
+                // only the default server uses the top-level token
+                let deadline = liveness_deadline(&self.heartbeat);
+                let server = &mut self.servers[0];
+                server.reconnect_at = deadline;
+                if server.conn.update_credentials(token) {
+                    self.send_status(0, ConnectionState::Connecting).await;
+                }
+            }
+        }
+    }
+
+    async fn update_config(&mut self, config: Config) {
+        log::info!("applying new config: {config:?}");
+        self.store.reconfigure(&config.history);
+        self.max_clip_size
+            .store(config.transfer.max_clip_size, Ordering::Relaxed);
+        self.heartbeat = config.heartbeat.clone();
+        self.timer = tick_interval(&self.heartbeat);
+        self.routing = config.routing;
+
+        let restart_lan = match self.lan.as_mut() {
+            Some(lan) => {
+                lan.set_max_clip_size(config.transfer.max_clip_size);
+                !config.lan.enabled || lan.needs_restart(&config.lan, config.passphrase.as_deref())
+            }
+            None => config.lan.enabled,
+        };
+        if restart_lan {
+            // sockets have to be closed before binding the same port again
+            self.lan = None;
+            self.lan = start_lan(&config, &self.lan_seen);
+        }
+
+        let mut old = std::mem::take(&mut self.servers);
+        for profile in config.profiles() {
+            let idx = self.servers.len();
+            let existing = old.iter().position(|server| server.profile == profile.name);
+            let Some(existing) = existing else {
+                log::info!("server profile {:?} has been added", profile.name);
+                let server =
+                    Server::new(profile.name, profile.config, profile.send, &self.heartbeat);
+                self.servers.push(server);
+                self.send_status(idx, ConnectionState::Connecting).await;
+                continue;
+            };
+
+            let mut server = old.remove(existing);
+            server.send = profile.send;
+            server.reconnect_at = liveness_deadline(&self.heartbeat);
+            let was_connected = server.conn.is_connected();
+            let reconnect = server.conn.update_config(profile.config);
+            self.servers.push(server);
+            if reconnect {
+                if was_connected {
+                    self.send_status(idx, ConnectionState::Disconnected).await;
+                }
+                self.send_status(idx, ConnectionState::Connecting).await;
+            }
+        }
+
+        for server in old {
+            log::info!("server profile {:?} has been removed", server.profile);
+            let status = ConnectionStatus::new(ConnectionState::Disconnected, None);
+            self.send_event(Event::ProfileStatusChanged(server.profile, status))
+                .await;
+            for item in server.conn.close() {
+                self.clip_failed(item, "server profile has been removed")
+                    .await;
+            }
+        }
+        self.send_event(Event::StatusChanged(self.overall_status()))
+            .await;
+    }
+
+    async fn reconnect_now(&mut self) {
+        for idx in 0..self.servers.len() {
+            let server = &mut self.servers[idx];
+            server.reconnect_at = liveness_deadline(&self.heartbeat);
+            if server.conn.reconnect_now() {
+                self.send_status(idx, ConnectionState::Connecting).await;
+            }
+        }
+    }
+
+    // Existing socket may be bound to an interface that is gone (or silently dead
+    // after sleep), so it's dropped without waiting for the liveness timeout.
+    async fn network_changed(&mut self) {
+        log::info!("network has changed, reconnecting");
+        for idx in 0..self.servers.len() {
+            let was_connected = self.servers[idx].conn.is_connected();
+            self.servers[idx].conn.disconnect();
+            // hosts must see the connection loss, not only the new attempt
+            if was_connected {
+                self.send_status(idx, ConnectionState::Disconnected).await;
+            }
+        }
+        self.reconnect_now().await;
+    }
+
+    async fn send_clip(
+        &mut self,
+        clip: Clip,
+        reply: Sender<bool>,
+        on_delivered: Option<OnDelivered>,
+    ) {
         let is_new = self.store.add(&clip);
         if reply.send(is_new).is_err() {
             log::error!("failed to send reply back: channel is closed");
             return;
         }
-        if is_new {
-            log::info!("new clip from local keyboard: {clip:?}");
-            self.conn.send(clip).await;
+        if !is_new {
+            return;
+        }
+
+        log::info!("new clip from local keyboard: {clip:?}");
+        if let Some(lan) = self.lan.as_mut() {
+            lan.send(&clip);
+        }
+        let item = Outgoing::new(clip);
+        let targets = self.route();
+        if targets.is_empty() {
+            log::warn!("none of the servers accepts local clips");
+            if let Some(on_delivered) = on_delivered {
+                on_delivered(Err(anyhow!("none of the servers accepts local clips")));
+            }
+            return;
+        }
+
+        self.deliveries.insert(
+            item.id,
+            Delivery {
+                on_delivered,
+                pending: targets.len(),
+                delivered: false,
+            },
+        );
+        for idx in targets {
+            self.servers[idx].conn.send(item.clone());
+        }
+    }
+
+    // Indices of servers that a local clip is sent to
+    fn route(&self) -> Vec<usize> {
+        let candidates = self
+            .servers
+            .iter()
+            .enumerate()
+            .filter(|(_, server)| server.send)
+            .map(|(idx, _)| idx);
+
+        match self.routing {
+            RoutingPolicy::All => candidates.collect(),
+            RoutingPolicy::Failover => {
+                let candidates = candidates.collect::<Vec<_>>();
+                let connected = candidates
+                    .iter()
+                    .find(|idx| self.servers[**idx].conn.is_connected());
+                connected
+                    .or(candidates.first())
+                    .copied()
+                    .into_iter()
+                    .collect()
+            }
         }
     }
 
@@ -92,23 +347,105 @@ impl MainLoop {
         if let Err(err) = self.pipe_writer.write(b"1") {
             log::error!("failed to trigger notification via pipe writer: {err:?}")
         }
+        if let Some(callback) = self.callback.as_ref() {
+            callback();
+        }
+    }
+
+    // Reports the status of a single server followed by the overall status
+    async fn send_status(&mut self, idx: usize, state: ConnectionState) {
+        let server = &mut self.servers[idx];
+        server.status = ConnectionStatus::new(state, server.conn.retry_in());
+        let event = Event::ProfileStatusChanged(server.profile.clone(), server.status);
+        self.send_event(event).await;
+        self.send_event(Event::StatusChanged(self.overall_status()))
+            .await;
+    }
+
+    fn overall_status(&self) -> ConnectionStatus {
+        ConnectionStatus::combine(self.servers.iter().map(|server| server.status))
+    }
+
+    async fn clip_delivered(&mut self, Outgoing { id, clip }: Outgoing) {
+        log::info!("clip {id} has been delivered");
+        let Some(delivery) = self.deliveries.get_mut(&id) else {
+            // restored from disk after restart
+            self.send_event(Event::ClipDelivered(clip)).await;
+            return;
+        };
+        delivery.pending -= 1;
+        let first = !delivery.delivered;
+        delivery.delivered = true;
+        let on_delivered = delivery.on_delivered.take();
+        if delivery.pending == 0 {
+            self.deliveries.remove(&id);
+        }
+        if first {
+            if let Some(on_delivered) = on_delivered {
+                on_delivered(Ok(()));
+            }
+            self.send_event(Event::ClipDelivered(clip)).await;
+        }
+    }
+
+    async fn clip_failed(&mut self, Outgoing { id, clip }: Outgoing, reason: &str) {
+        log::warn!("clip {id} hasn't been delivered: {reason}");
+        let Some(delivery) = self.deliveries.get_mut(&id) else {
+            // restored from disk after restart
+            self.send_event(Event::ClipFailed(clip)).await;
+            return;
+        };
+        delivery.pending -= 1;
+        if delivery.pending > 0 {
+            return;
+        }
+        let Some(delivery) = self.deliveries.remove(&id) else {
+            return;
+        };
+        if delivery.delivered {
+            return;
+        }
+        if let Some(on_delivered) = delivery.on_delivered {
+            on_delivered(Err(anyhow!("clip hasn't been delivered: {reason}")));
+        }
+        self.send_event(Event::ClipFailed(clip)).await;
     }
 
-    async fn process_event(&mut self, event: ConnectionEvent) {
+    async fn process_event(&mut self, idx: usize, event: ConnectionEvent) {
         match event {
-            ConnectionEvent::Connecting => {}
-            ConnectionEvent::SendingAuthRequest => {}
-            ConnectionEvent::WaitingForAuthResponse => {}
+            ConnectionEvent::Connecting => {
+                self.send_status(idx, ConnectionState::Connecting).await;
+            }
+            ConnectionEvent::SendingAuthRequest => {
+                self.send_status(idx, ConnectionState::SendingAuthRequest)
+                    .await;
+            }
+            ConnectionEvent::WaitingForAuthResponse => {
+                self.send_status(idx, ConnectionState::WaitingForAuthResponse)
+                    .await;
+            }
             ConnectionEvent::Connected => {
-                self.send_event(Event::ConnectivityChanged(true)).await;
-                self.conn.send_pending_if_any().await;
+                self.servers[idx].next_ping_at = Instant::now();
+                // the deadline could have passed while the server was unreachable
+                self.servers[idx].reconnect_at = liveness_deadline(&self.heartbeat);
+                self.send_status(idx, ConnectionState::Connected).await;
             }
             ConnectionEvent::Disconnected => {
-                self.send_event(Event::ConnectivityChanged(false)).await;
+                self.send_status(idx, ConnectionState::Disconnected).await;
+            }
+            ConnectionEvent::AuthFailed(reason) => {
+                self.send_status(idx, ConnectionState::AuthFailed).await;
+                self.send_event(Event::AuthFailed(reason)).await;
             }
-            ConnectionEvent::AuthFailed => {}
             ConnectionEvent::ReceivedPing => {
-                self.reconnect_at = fifteen_secs_from_now();
+                self.servers[idx].reconnect_at = liveness_deadline(&self.heartbeat);
+            }
+            ConnectionEvent::ReceivedPong(rtt) => {
+                self.servers[idx].reconnect_at = liveness_deadline(&self.heartbeat);
+                if let Some(rtt) = rtt {
+                    log::info!("[ws] latency: {rtt:?}");
+                    self.send_event(Event::Latency(rtt)).await;
+                }
             }
             ConnectionEvent::ReceivedClip(clip) => {
                 if self.store.add(&clip) {
@@ -116,18 +453,114 @@ impl MainLoop {
                     self.send_event(Event::NewClip(clip)).await;
                 }
             }
+            ConnectionEvent::DecryptionFailed => {
+                log::warn!("received clip can't be decrypted, check your passphrase");
+                self.send_event(Event::DecryptionFailed).await;
+            }
+            ConnectionEvent::ClipSent => {}
+            ConnectionEvent::UnknownAck => {}
+            ConnectionEvent::ClipDelivered(item) => self.clip_delivered(item).await,
+            ConnectionEvent::ClipFailed(item, reason) => self.clip_failed(item, &reason).await,
+            ConnectionEvent::ClipRejected => {
+                log::warn!("received clip has been rejected");
+                self.send_event(Event::ClipRejected).await;
+            }
+            ConnectionEvent::Progress(progress) => {
+                if progress.direction == Direction::Upload {
+                    // server doesn't get a chance to PING us while we are sending chunks
+                    self.servers[idx].reconnect_at = liveness_deadline(&self.heartbeat);
+                }
+                self.send_event(Event::Progress(progress)).await;
+            }
+        }
+    }
+
+    async fn process_lan_event(&mut self, event: LanEvent) {
+        match event {
+            LanEvent::ReceivedClip(clip) => {
+                if self.store.add(&clip) {
+                    log::info!("new clip from lan: {clip:?}");
+                    self.send_event(Event::NewClip(clip)).await;
+                }
+            }
+            LanEvent::DecryptionFailed => {
+                log::warn!("clip from lan can't be decrypted, check your passphrase");
+                self.send_event(Event::DecryptionFailed).await;
+            }
+            LanEvent::Ignored => {}
         }
     }
 
     async fn tick(&mut self) {
-        if self.reconnect_at < Instant::now() {
-            self.reconnect_at = fifteen_secs_from_now();
-            self.conn.disconnect();
-            self.send_event(Event::ConnectivityChanged(false)).await;
+        if let Some(config) = self.watcher.as_mut().and_then(ConfigWatcher::poll) {
+            self.update_config(config).await;
         }
+        if let Some(lan) = self.lan.as_mut() {
+            lan.tick();
+        }
+
+        let now = Instant::now();
+        let ping_interval = self.heartbeat.ping_interval_secs;
+        for idx in 0..self.servers.len() {
+            let server = &mut self.servers[idx];
+            if server.conn.is_auth_failed() {
+                continue;
+            }
+            // attempts that are still in progress are limited by the connect timeout
+            if server.conn.is_connected() && server.reconnect_at < now {
+                server.reconnect_at = liveness_deadline(&self.heartbeat);
+                server.conn.disconnect();
+                self.send_status(idx, ConnectionState::Disconnected).await;
+                continue;
+            }
+
+            if ping_interval > 0 && server.conn.is_connected() && server.next_ping_at <= now {
+                server.next_ping_at = now + Duration::from_secs(ping_interval);
+                server.conn.ping();
+            }
+        }
+    }
+}
+
+// Waits for the next event of any server, returns it along with the index of the server
+async fn next_event(servers: &mut [Server]) -> (usize, ConnectionEvent) {
+    let recvs = servers
+        .iter_mut()
+        .map(|server| Box::pin(server.conn.recv()));
+    let (event, idx, _) = select_all(recvs).await;
+    (idx, event)
+}
+
+async fn next_lan_event(lan: &mut Option<Lan>) -> LanEvent {
+    match lan {
+        Some(lan) => lan.recv().await,
+        None => std::future::pending().await,
     }
 }
 
-fn fifteen_secs_from_now() -> Instant {
-    Instant::now() + Duration::from_secs(15)
+fn start_lan(config: &Config, seen: &SeenFrames) -> Option<Lan> {
+    if !config.lan.enabled {
+        return None;
+    }
+    match Lan::new(
+        &config.lan,
+        &config.name,
+        config.passphrase.as_deref(),
+        config.transfer.max_clip_size,
+        Arc::clone(seen),
+    ) {
+        Ok(lan) => Some(lan),
+        Err(err) => {
+            log::error!("[lan] LAN sync is disabled: {err:?}");
+            None
+        }
+    }
+}
+
+fn liveness_deadline(heartbeat: &HeartbeatConfig) -> Instant {
+    Instant::now() + Duration::from_secs(heartbeat.liveness_timeout_secs)
+}
+
+fn tick_interval(heartbeat: &HeartbeatConfig) -> Interval {
+    interval(Duration::from_millis(heartbeat.tick_interval_ms.max(1)))
 }
@@ -1,32 +1,139 @@
-use crate::ffi::string_to_cstring;
+use crate::{
+    ConnectionState, ConnectionStatus, Direction, Progress, Update,
+    ffi::{flavor_to_ptrs, free_bytes, free_cstring, string_to_cstring},
+};
 use std::ffi::c_char;
 
 #[repr(C)]
 #[derive(Debug)]
-/// Represents a result of polling
+/// Represents a result of polling.
+/// Must be released with `mpclipboard_output_free`.
 pub struct Output {
-    /// Optional (NULLable) text received from the server
+    /// Optional (NULLable) text received from the server,
+    /// set only if the best supported flavor is `text/plain`
     pub text: *mut c_char,
-    /// Optional (NULLable) flag of the connectivity state
-    pub connectivity: *mut bool,
+    /// Optional (NULLable) MIME type of the best supported non-text flavor
+    pub mime: *mut c_char,
+    /// Optional (NULLable) content of the best supported non-text flavor
+    pub data: *mut u8,
+    /// Length of `data`
+    pub len: usize,
+    /// Set if `connectivity` has changed
+    pub has_connectivity: bool,
+    /// Connectivity state
+    pub connectivity: bool,
+    /// Set if `status` has changed
+    pub has_status: bool,
+    /// Latest status of the connection,
+    /// unlike `connectivity` it's also set for intermediate states (e.g. "connecting")
+    pub status: ConnectionStatus,
+    /// Optional (NULLable) reason of the latest authentication failure
+    /// (if the server has sent it), see `mpclipboard_handle_update_credentials`
+    pub auth_error: *mut c_char,
+    /// Set if `latency_ms` has been measured
+    pub has_latency: bool,
+    /// Latest round-trip time to the server in milliseconds
+    pub latency_ms: u64,
+    /// Set if `progress` has changed
+    pub has_progress: bool,
+    /// Latest progress of a large clip transfer
+    pub progress: Progress,
+    /// Set if a received clip couldn't be decrypted (most likely passphrases don't match)
+    pub decryption_failed: bool,
+    /// Set if a received clip has been dropped because it's malformed or too large
+    pub clip_rejected: bool,
 }
 
 impl Output {
     pub(crate) fn null() -> Self {
         Self {
             text: std::ptr::null_mut(),
-            connectivity: std::ptr::null_mut(),
+            mime: std::ptr::null_mut(),
+            data: std::ptr::null_mut(),
+            len: 0,
+            has_connectivity: false,
+            connectivity: false,
+            has_status: false,
+            status: ConnectionStatus {
+                state: ConnectionState::Disconnected,
+                retry_in_ms: 0,
+            },
+            auth_error: std::ptr::null_mut(),
+            has_latency: false,
+            latency_ms: 0,
+            has_progress: false,
+            progress: Progress {
+                direction: Direction::Upload,
+                transferred: 0,
+                total: 0,
+            },
+            decryption_failed: false,
+            clip_rejected: false,
         }
     }
 
-    pub(crate) fn new(text: Option<String>, connectivity: Option<bool>) -> Self {
+    pub(crate) fn new(update: Update, supported: &[String]) -> Self {
+        let Update {
+            clip,
+            connectivity,
+            status,
+            auth_error,
+            latency,
+            progress,
+            decryption_failed,
+            clip_rejected,
+            ..
+        } = update;
+
         let mut out = Self::null();
-        if let Some(text) = text {
-            out.text = string_to_cstring(text);
+        if let Some(flavor) = clip.as_ref().and_then(|clip| clip.best_flavor(supported)) {
+            let flavor = flavor_to_ptrs(flavor.clone());
+            out.text = flavor.text;
+            out.mime = flavor.mime;
+            out.data = flavor.data;
+            out.len = flavor.len;
+        } else if let Some(clip) = clip {
+            log::warn!("none of the flavors of {clip:?} is supported, skipping");
         }
         if let Some(connectivity) = connectivity {
-            out.connectivity = Box::leak(Box::new(connectivity));
+            out.has_connectivity = true;
+            out.connectivity = connectivity;
+        }
+        if let Some(status) = status {
+            out.has_status = true;
+            out.status = status;
+        }
+        if let Some(auth_error) = auth_error {
+            out.auth_error = string_to_cstring(auth_error);
+        }
+        if let Some(latency) = latency {
+            out.has_latency = true;
+            out.latency_ms = latency.as_millis() as u64;
         }
+        if let Some(progress) = progress {
+            out.has_progress = true;
+            out.progress = progress;
+        }
+        out.decryption_failed = decryption_failed;
+        out.clip_rejected = clip_rejected;
         out
     }
 }
+
+/// Releases output returned by `mpclipboard_handle_poll`.
+/// Pointer fields that have been released separately
+/// (e.g. via `mpclipboard_string_free`) must be set to NULL beforehand.
+///
+/// # Safety
+///
+/// `output` must be a value returned by `mpclipboard_handle_poll`
+/// that hasn't been released yet
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mpclipboard_output_free(output: Output) {
+    unsafe {
+        free_cstring(output.text);
+        free_cstring(output.mime);
+        free_bytes(output.data, output.len);
+        free_cstring(output.auth_error);
+    }
+}
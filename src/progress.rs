@@ -0,0 +1,21 @@
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Direction of a transfer
+pub enum Direction {
+    /// Local clip is being sent to the server
+    Upload = 0,
+    /// Clip is being received from the server
+    Download = 1,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Progress of a large clip transfer (only large clips are sent in chunks and report progress)
+pub struct Progress {
+    /// Direction of the transfer
+    pub direction: Direction,
+    /// Number of bytes transferred so far
+    pub transferred: usize,
+    /// Total number of bytes
+    pub total: usize,
+}
@@ -0,0 +1,207 @@
+use crate::{DEFAULT_PROFILE, QueueConfig, QueuePolicy, clip::Clip, disk::Disk};
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+};
+
+// A clip waiting to be sent, `id` is what the server acknowledges once the clip is relayed
+// (the same clip sent to several servers has the same id)
+#[derive(Debug, Clone)]
+pub(crate) struct Outgoing {
+    pub(crate) id: u64,
+    pub(crate) clip: Clip,
+}
+
+impl Outgoing {
+    pub(crate) fn new(clip: Clip) -> Self {
+        Self {
+            id: fastrand::u64(..),
+            clip,
+        }
+    }
+}
+
+pub(crate) struct Queue {
+    items: VecDeque<Outgoing>,
+    // clips taken by `pop_front` that are kept on disk until they are acknowledged,
+    // tracked only if the queue is persisted
+    in_flight: Vec<Outgoing>,
+    policy: QueuePolicy,
+    size: usize,
+    disk: Option<Disk>,
+    config: QueueConfig,
+}
+
+impl Queue {
+    pub(crate) fn new(config: &QueueConfig, profile: &str) -> Self {
+        let mut queue = Self {
+            items: VecDeque::new(),
+            in_flight: vec![],
+            policy: config.policy,
+            size: config.size.max(1),
+            disk: None,
+            config: config.clone(),
+        };
+
+        if config.persist {
+            let (path, file_name) = profile_path(config.path.as_deref(), profile);
+            match Disk::open(path.as_deref(), &file_name) {
+                Ok(disk) => queue.load(disk),
+                Err(err) => log::error!("[queue] outbound queue won't be persisted: {err:?}"),
+            }
+        }
+
+        queue
+    }
+
+    // Ids are not persisted, nobody waits for delivery of clips queued before a restart
+    fn load(&mut self, mut disk: Disk) {
+        let clips = match disk.load() {
+            Ok(clips) => clips,
+            Err(err) => {
+                // the file is left as is, rewriting it would wipe the queue
+                log::error!("[queue] outbound queue won't be persisted: {err:?}");
+                return;
+            }
+        };
+        for clip in clips {
+            self.items.push_back(Outgoing::new(clip));
+            self.apply_policy();
+        }
+        log::info!("[queue] loaded {} clip(s) from disk", self.items.len());
+
+        // drops corrupted records and clips that don't fit the policy
+        if let Err(err) = disk.rewrite(self.items.iter().map(|item| &item.clip)) {
+            log::error!("[queue] failed to compact outbound queue: {err:?}");
+        }
+        self.disk = Some(disk);
+    }
+
+    // Policy is applied right away, switching persistence on/off
+    // (or moving the file) requires a restart.
+    // Returns clips dropped because of the new policy
+    #[must_use]
+    pub(crate) fn reconfigure(&mut self, config: &QueueConfig) -> Vec<Outgoing> {
+        if config.persist != self.config.persist || config.path != self.config.path {
+            log::warn!("[queue] outbound queue persistence settings are applied after restart");
+        }
+        self.policy = config.policy;
+        self.size = config.size.max(1);
+        self.config = config.clone();
+        let dropped = self.apply_policy();
+        self.compact();
+        dropped
+    }
+
+    // Returns clips dropped because of the queue policy
+    #[must_use]
+    pub(crate) fn push_back(&mut self, item: Outgoing) -> Vec<Outgoing> {
+        if let Some(disk) = self.disk.as_mut()
+            && let Err(err) = disk.append(&item.clip)
+        {
+            log::error!("[queue] failed to persist clip: {err:?}");
+        }
+        self.items.push_back(item);
+        let dropped = self.apply_policy();
+        self.compact();
+        dropped
+    }
+
+    // Returns a clip that hasn't been sent (or acknowledged) back to the head of the queue,
+    // it's still on disk since `pop_front`
+    #[must_use]
+    pub(crate) fn push_front(&mut self, item: Outgoing) -> Vec<Outgoing> {
+        self.in_flight.retain(|in_flight| in_flight.id != item.id);
+        self.items.push_front(item);
+        let dropped = self.apply_policy();
+        self.compact();
+        dropped
+    }
+
+    // Takes every clip out of the queue (e.g. when the server is removed from the config)
+    pub(crate) fn drain(&mut self) -> Vec<Outgoing> {
+        let items = self.items.drain(..).collect();
+        self.in_flight.clear();
+        self.compact();
+        items
+    }
+
+    // The clip stays on disk until `complete` is called,
+    // so it's sent again after a restart if it hasn't been acknowledged
+    pub(crate) fn pop_front(&mut self) -> Option<Outgoing> {
+        let item = self.items.pop_front()?;
+        if self.disk.is_some() {
+            self.in_flight.push(item.clone());
+        }
+        Some(item)
+    }
+
+    // Removes a clip taken by `pop_front` from disk once it's delivered (or failed for good)
+    pub(crate) fn complete(&mut self, id: u64) {
+        let len = self.in_flight.len();
+        self.in_flight.retain(|item| item.id != id);
+        if self.in_flight.len() != len {
+            self.compact();
+        }
+    }
+
+    fn apply_policy(&mut self) -> Vec<Outgoing> {
+        let limit = match self.policy {
+            QueuePolicy::KeepAll => return vec![],
+            QueuePolicy::KeepLastN => self.size,
+            QueuePolicy::KeepLatestOnly => 1,
+        };
+        let mut dropped = vec![];
+        while self.items.len() > limit {
+            if let Some(item) = self.items.pop_front() {
+                log::info!("[queue] dropping {:?} from outbound queue", item.clip);
+                dropped.push(item);
+            }
+        }
+        dropped
+    }
+
+    // Sent and dropped clips stay in the file until there are as many of them as live ones
+    // (or there are no live ones left), this way the whole file is rarely rewritten
+    fn compact(&mut self) {
+        let Some(disk) = self.disk.as_mut() else {
+            return;
+        };
+        let live = self.in_flight.len() + self.items.len();
+        let result = if live == 0 && disk.lines() > 0 {
+            disk.clear()
+        } else if live > 0 && disk.lines() >= live * 2 {
+            disk.rewrite(
+                self.in_flight
+                    .iter()
+                    .chain(self.items.iter())
+                    .map(|item| &item.clip),
+            )
+        } else {
+            Ok(())
+        };
+        if let Err(err) = result {
+            log::error!("[queue] failed to compact outbound queue: {err:?}");
+        }
+    }
+}
+
+// Every server profile has its own queue file, the default one keeps the original name
+fn profile_path(path: Option<&Path>, profile: &str) -> (Option<PathBuf>, String) {
+    if profile == DEFAULT_PROFILE {
+        return (path.map(Path::to_path_buf), String::from("queue.jsonl"));
+    }
+    let file_name = format!("queue-{profile}.jsonl");
+    let path = path.map(|path| {
+        let stem = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("queue");
+        let ext = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("jsonl");
+        path.with_file_name(format!("{stem}-{profile}.{ext}"))
+    });
+    (path, file_name)
+}
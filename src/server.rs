@@ -0,0 +1,478 @@
+use crate::{Clip, chunks, compression::Compression};
+use anyhow::{Context as _, Result, bail};
+use futures::{SinkExt as _, StreamExt as _};
+use http::Uri;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{Sender, channel, error::TrySendError},
+    task::JoinHandle,
+    time::{Instant, Interval, interval_at, timeout},
+};
+use tokio_util::sync::CancellationToken;
+use tokio_websockets::{Message, ServerBuilder, WebSocketStream};
+
+// Server side of the protocol spoken by `Connection`:
+// 1. client sends `Auth` (TEXT), server replies with `AuthReply` (TEXT)
+//    and closes the connection if the token is unknown
+// 2. every clip (TEXT JSON with an `id` or BINARY chunks) is relayed as is
+//    to all other authenticated clients, so encrypted clips stay opaque to the server
+// 3. once the clip (or its last chunk) is relayed the sender gets `{ "ack": id }`
+// 4. server sends PINGs, clients that stay silent for two intervals are disconnected,
+//    as well as clients that don't keep up with relayed messages
+
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Settings of the bundled relay server
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct RelayConfig {
+    /// Address to listen on, port 0 picks a random free port
+    pub bind: SocketAddr,
+
+    /// Tokens that clients are allowed to authenticate with
+    pub tokens: Vec<String>,
+
+    /// Whether clients that support compression are allowed to use it
+    pub compression: bool,
+
+    /// Whether relayed clips are acknowledged
+    pub acks: bool,
+
+    /// Max size of a chunked clip in bytes, larger clips are rejected
+    pub max_clip_size: usize,
+
+    /// Max number of messages waiting to be sent to a single client,
+    /// clients that fall further behind are disconnected
+    pub client_queue_size: usize,
+
+    /// Interval between PINGs in seconds, clients that send nothing
+    /// (including PONGs) for two intervals are disconnected. 0 disables PINGs
+    pub ping_interval_secs: u64,
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            bind: SocketAddr::from(([127, 0, 0, 1], 3000)),
+            tokens: vec![],
+            compression: true,
+            acks: true,
+            max_clip_size: 16 * 1024 * 1024,
+            client_queue_size: 256,
+            ping_interval_secs: 30,
+        }
+    }
+}
+
+impl std::fmt::Debug for RelayConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RelayConfig")
+            .field("bind", &self.bind)
+            .field("tokens", &vec!["******"; self.tokens.len()])
+            .field("compression", &self.compression)
+            .field("acks", &self.acks)
+            .field("max_clip_size", &self.max_clip_size)
+            .field("client_queue_size", &self.client_queue_size)
+            .field("ping_interval_secs", &self.ping_interval_secs)
+            .finish()
+    }
+}
+
+impl RelayConfig {
+    /// Reads the config from given TOML file
+    pub fn read(path: &str) -> Result<Self> {
+        let content =
+            std::fs::read_to_string(path).with_context(|| format!("failed to read {path}"))?;
+        toml::from_str(&content).with_context(|| format!("failed to parse {path}"))
+    }
+}
+
+#[derive(Deserialize)]
+struct Auth {
+    name: String,
+    token: String,
+    // unknown algorithms (offered by newer clients) are skipped
+    #[serde(default)]
+    compression: Vec<serde_json::Value>,
+    #[serde(default)]
+    acks: bool,
+}
+
+#[derive(Serialize)]
+struct AuthReply {
+    success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    compression: Option<Compression>,
+    acks: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Ack {
+    ack: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Ack {
+    fn ok(id: u64) -> Self {
+        Self {
+            ack: id,
+            error: None,
+        }
+    }
+
+    fn error(id: u64, error: &str) -> Self {
+        Self {
+            ack: id,
+            error: Some(error.to_string()),
+        }
+    }
+}
+
+struct Shared {
+    config: RelayConfig,
+    next_id: AtomicU64,
+    clients: Mutex<HashMap<u64, Sender<Message>>>,
+}
+
+impl Shared {
+    // Clients with a full queue are removed (which closes their connection),
+    // dropping a single message would corrupt chunked clips
+    fn broadcast(&self, from: u64, message: &Message) {
+        let mut clients = self.clients.lock().expect("clients lock is poisoned");
+        clients.retain(|id, tx| {
+            if *id == from {
+                return true;
+            }
+            match tx.try_send(message.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    log::warn!("[server] client {id} doesn't keep up, disconnecting");
+                    false
+                }
+                Err(TrySendError::Closed(_)) => false,
+            }
+        });
+    }
+}
+
+// Removes the client from the list of receivers once its connection is gone
+struct Registration<'a> {
+    shared: &'a Shared,
+    id: u64,
+}
+
+impl Drop for Registration<'_> {
+    fn drop(&mut self) {
+        self.shared
+            .clients
+            .lock()
+            .expect("clients lock is poisoned")
+            .remove(&self.id);
+    }
+}
+
+/// WebSocket server that relays clips between clients.
+/// Can be run both standalone (see `mpclipboard-server` binary)
+/// and in-process (e.g. as a test fixture).
+pub struct Relay {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    token: CancellationToken,
+    task: Option<JoinHandle<()>>,
+}
+
+impl Relay {
+    /// Binds to `config.bind` and starts accepting clients on the current Tokio runtime
+    pub async fn start(config: RelayConfig) -> Result<Self> {
+        if config.tokens.is_empty() {
+            log::warn!("[server] no tokens are configured, all clients will be rejected");
+        }
+        let listener = TcpListener::bind(config.bind)
+            .await
+            .with_context(|| format!("failed to bind {}", config.bind))?;
+        let addr = listener.local_addr()?;
+        log::info!("[server] listening on {addr}");
+
+        let shared = Arc::new(Shared {
+            config,
+            next_id: AtomicU64::new(0),
+            clients: Mutex::new(HashMap::new()),
+        });
+        let token = CancellationToken::new();
+        let task = tokio::spawn(accept_loop(listener, Arc::clone(&shared), token.clone()));
+
+        Ok(Self {
+            addr,
+            shared,
+            token,
+            task: Some(task),
+        })
+    }
+
+    /// Returns the address the server is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Returns URI that clients can connect to (e.g. `ws://127.0.0.1:3000`)
+    pub fn uri(&self) -> Uri {
+        format!("ws://{}", self.addr)
+            .parse()
+            .expect("socket address is a valid URI authority")
+    }
+
+    /// Returns the number of authenticated clients
+    pub fn clients(&self) -> usize {
+        self.shared
+            .clients
+            .lock()
+            .expect("clients lock is poisoned")
+            .len()
+    }
+
+    /// Stops accepting clients and drops all connections
+    pub async fn stop(mut self) {
+        self.token.cancel();
+        if let Some(task) = self.task.take()
+            && let Err(err) = task.await
+        {
+            log::error!("[server] accept loop has crashed: {err:?}");
+        }
+        log::info!("[server] stopped");
+    }
+}
+
+impl Drop for Relay {
+    fn drop(&mut self) {
+        self.token.cancel();
+    }
+}
+
+async fn accept_loop(listener: TcpListener, shared: Arc<Shared>, token: CancellationToken) {
+    loop {
+        let (stream, addr) = tokio::select! {
+            _ = token.cancelled() => break,
+            accepted = listener.accept() => match accepted {
+                Ok(pair) => pair,
+                Err(err) => {
+                    log::error!("[server] failed to accept connection: {err:?}");
+                    continue;
+                }
+            },
+        };
+
+        let shared = Arc::clone(&shared);
+        let token = token.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = token.cancelled() => {}
+                result = serve(stream, addr, &shared) => {
+                    if let Err(err) = result {
+                        log::error!("[server] {addr}: {err:?}");
+                    }
+                }
+            }
+        });
+    }
+}
+
+async fn serve(stream: TcpStream, addr: SocketAddr, shared: &Shared) -> Result<()> {
+    let (_, mut ws) = ServerBuilder::new()
+        .accept(stream)
+        .await
+        .context("WebSocket handshake has failed")?;
+
+    let auth = match timeout(AUTH_TIMEOUT, ws.next()).await {
+        Ok(Some(message)) => message?,
+        Ok(None) => bail!("connection is closed before authentication"),
+        Err(_) => bail!("no auth request within {AUTH_TIMEOUT:?}"),
+    };
+    let Some(auth) = auth
+        .as_text()
+        .and_then(|text| serde_json::from_str::<Auth>(text).ok())
+    else {
+        reject(&mut ws, "malformed auth request").await;
+        bail!("malformed auth request");
+    };
+    if !shared.config.tokens.contains(&auth.token) {
+        reject(&mut ws, "invalid token").await;
+        bail!("{} has sent an invalid token", auth.name);
+    }
+
+    let compression = auth
+        .compression
+        .into_iter()
+        .filter_map(|offered| serde_json::from_value::<Compression>(offered).ok())
+        .find(|_| shared.config.compression);
+    let acks = auth.acks && shared.config.acks;
+    send_json(
+        &mut ws,
+        &AuthReply {
+            success: true,
+            compression,
+            acks,
+            reason: None,
+        },
+    )
+    .await?;
+    log::info!("[server] {} has connected from {addr}", auth.name);
+
+    let (tx, mut rx) = channel(shared.config.client_queue_size.max(1));
+    let id = shared.next_id.fetch_add(1, Ordering::Relaxed);
+    shared
+        .clients
+        .lock()
+        .expect("clients lock is poisoned")
+        .insert(id, tx);
+    let _registration = Registration { shared, id };
+    let mut session = Session {
+        id,
+        rejected: HashSet::new(),
+    };
+
+    let ping_interval = Duration::from_secs(shared.config.ping_interval_secs);
+    let mut ping = (!ping_interval.is_zero())
+        .then(|| interval_at(Instant::now() + ping_interval, ping_interval));
+    let mut last_seen = Instant::now();
+
+    loop {
+        tokio::select! {
+            message = ws.next() => {
+                let Some(message) = message else {
+                    break;
+                };
+                last_seen = Instant::now();
+                if let Some(ack) = session.relay(message?, shared)
+                    && acks
+                {
+                    send_json(&mut ws, &ack).await?;
+                }
+            }
+
+            message = rx.recv() => {
+                let Some(message) = message else {
+                    log::warn!("[server] {} has been disconnected for lagging behind", auth.name);
+                    break;
+                };
+                send(&mut ws, message).await?;
+            }
+
+            _ = tick(&mut ping) => {
+                if last_seen.elapsed() > ping_interval * 2 {
+                    bail!("{} hasn't responded for {:?}", auth.name, last_seen.elapsed());
+                }
+                send(&mut ws, Message::ping(Vec::new())).await?;
+            }
+        }
+    }
+
+    log::info!("[server] {} has disconnected", auth.name);
+    Ok(())
+}
+
+async fn reject(ws: &mut WebSocketStream<TcpStream>, reason: &str) {
+    let reply = AuthReply {
+        success: false,
+        compression: None,
+        acks: false,
+        reason: Some(reason.to_string()),
+    };
+    if let Err(err) = send_json(ws, &reply).await {
+        log::error!("[server] failed to send auth reply: {err:?}");
+    }
+}
+
+// Never completes if PINGs are disabled
+async fn tick(ping: &mut Option<Interval>) {
+    match ping {
+        Some(ping) => {
+            ping.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+// A client that doesn't read its socket would block the session forever
+async fn send(ws: &mut WebSocketStream<TcpStream>, message: Message) -> Result<()> {
+    timeout(SEND_TIMEOUT, ws.send(message))
+        .await
+        .with_context(|| format!("client hasn't accepted a message within {SEND_TIMEOUT:?}"))??;
+    Ok(())
+}
+
+async fn send_json(ws: &mut WebSocketStream<TcpStream>, value: &impl Serialize) -> Result<()> {
+    let json = serde_json::to_string(value)?;
+    ws.send(Message::text(json)).await?;
+    Ok(())
+}
+
+struct Session {
+    id: u64,
+    // transfers that exceed max clip size, their chunks are dropped
+    rejected: HashSet<u64>,
+}
+
+impl Session {
+    // Relays the message to other clients, returns an ack for the sender (if any)
+    fn relay(&mut self, message: Message, shared: &Shared) -> Option<Ack> {
+        if let Some(text) = message.as_text() {
+            let Ok(json) = serde_json::from_str::<serde_json::Value>(text) else {
+                log::warn!("[server] dropping malformed TEXT message");
+                return None;
+            };
+            let id = json.get("id").and_then(serde_json::Value::as_u64);
+            if let Err(err) = serde_json::from_value::<Clip>(json) {
+                log::warn!("[server] dropping malformed clip: {err:?}");
+                return id.map(|id| Ack::error(id, "malformed clip"));
+            }
+            shared.broadcast(self.id, &message);
+            return id.map(Ack::ok);
+        }
+
+        // control frames are handled by the WebSocket stream
+        if !message.is_binary() {
+            return None;
+        }
+        let Some((header, _)) = chunks::parse(message.as_payload()) else {
+            // not chunked, so there's no id to acknowledge
+            shared.broadcast(self.id, &message);
+            return None;
+        };
+
+        let is_last = header.index + 1 >= header.count;
+        if header.index == 0 && header.total > shared.config.max_clip_size {
+            log::warn!(
+                "[server] rejecting clip {} ({} bytes, max is {})",
+                header.id,
+                header.total,
+                shared.config.max_clip_size
+            );
+            self.rejected.insert(header.id);
+        }
+        // the sender expects an ack only after the whole clip has been sent
+        if self.rejected.contains(&header.id) {
+            if !is_last {
+                return None;
+            }
+            self.rejected.remove(&header.id);
+            return Some(Ack::error(header.id, "clip is too large"));
+        }
+
+        shared.broadcast(self.id, &message);
+        is_last.then(|| Ack::ok(header.id))
+    }
+}
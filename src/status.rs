@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// State of the connection to the server
+pub enum ConnectionState {
+    /// WebSocket connection is being established
+    Connecting = 0,
+    /// Credentials are being sent to the server
+    SendingAuthRequest = 1,
+    /// Waiting for the server to accept credentials
+    WaitingForAuthResponse = 2,
+    /// Connected and authenticated
+    Connected = 3,
+    /// Server is unreachable or the connection is lost
+    Disconnected = 4,
+    /// Server has rejected credentials (e.g. bad token)
+    AuthFailed = 5,
+}
+
+impl ConnectionState {
+    fn rank(self) -> u8 {
+        match self {
+            Self::AuthFailed => 0,
+            Self::Disconnected => 1,
+            Self::Connecting => 2,
+            Self::SendingAuthRequest => 3,
+            Self::WaitingForAuthResponse => 4,
+            Self::Connected => 5,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Status of the connection to the server
+pub struct ConnectionStatus {
+    /// Current state
+    pub state: ConnectionState,
+    /// Time before the next reconnect attempt in milliseconds,
+    /// `0` if there's no scheduled attempt
+    pub retry_in_ms: u64,
+}
+
+impl ConnectionStatus {
+    pub(crate) fn new(state: ConnectionState, retry_in: Option<Duration>) -> Self {
+        Self {
+            state,
+            retry_in_ms: retry_in.map_or(0, |retry_in| retry_in.as_millis() as u64),
+        }
+    }
+
+    // Overall status of several connections: the most "alive" one wins,
+    // among disconnected ones it's the one that retries first
+    pub(crate) fn combine(statuses: impl IntoIterator<Item = Self>) -> Self {
+        statuses
+            .into_iter()
+            .max_by_key(|status| (status.state.rank(), std::cmp::Reverse(status.retry_in_ms)))
+            .unwrap_or(Self::new(ConnectionState::Disconnected, None))
+    }
+
+    /// Returns `true` if there's a connection and it's authenticated
+    pub fn is_connected(&self) -> bool {
+        self.state == ConnectionState::Connected
+    }
+
+    /// Returns time before the next reconnect attempt (if it's scheduled)
+    pub fn retry_in(&self) -> Option<Duration> {
+        (self.retry_in_ms > 0).then(|| Duration::from_millis(self.retry_in_ms))
+    }
+}
@@ -1,27 +1,144 @@
-use crate::clip::Clip;
+use crate::{HistoryConfig, clip::Clip, disk::Disk};
+use std::{
+    collections::VecDeque,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-#[derive(Default)]
 pub(crate) struct Store {
-    clip: Option<Clip>,
+    clips: VecDeque<Clip>,
+    capacity: usize,
+    max_age_ms: Option<u128>,
+    disk: Option<Disk>,
+    config: HistoryConfig,
 }
 
 impl Store {
-    pub(crate) fn new() -> Self {
-        Self::default()
+    pub(crate) fn new(config: &HistoryConfig) -> Self {
+        let mut store = Self {
+            clips: VecDeque::new(),
+            // the most recent clip is always kept, it's used for deduplication
+            capacity: config.size.max(1),
+            max_age_ms: config.max_age_secs.map(|secs| u128::from(secs) * 1_000),
+            disk: None,
+            config: config.clone(),
+        };
+
+        if config.persist {
+            match Disk::open(config.path.as_deref(), "history.jsonl") {
+                Ok(disk) => store.load(disk),
+                Err(err) => log::error!("[store] history won't be persisted: {err:?}"),
+            }
+        }
+
+        store
+    }
+
+    fn load(&mut self, mut disk: Disk) {
+        let mut clips = match disk.load() {
+            Ok(clips) => clips,
+            Err(err) => {
+                // the file is left as is, rewriting it would wipe the history
+                log::error!("[store] history won't be persisted: {err:?}");
+                return;
+            }
+        };
+        clips.sort_by_key(|clip| clip.timestamp);
+        for clip in clips {
+            self.insert(clip);
+        }
+        self.evict_expired();
+        log::info!("[store] loaded {} clip(s) from disk", self.clips.len());
+
+        // drops corrupted, duplicated and expired records
+        if let Err(err) = disk.rewrite(self.clips.iter()) {
+            log::error!("[store] failed to compact history: {err:?}");
+        }
+        self.disk = Some(disk);
+    }
+
+    // Limits are applied right away, switching persistence on/off
+    // (or moving the file) requires a restart
+    pub(crate) fn reconfigure(&mut self, config: &HistoryConfig) {
+        if config.persist != self.config.persist || config.path != self.config.path {
+            log::warn!("[store] history persistence settings are applied after restart");
+        }
+        self.capacity = config.size.max(1);
+        self.max_age_ms = config.max_age_secs.map(|secs| u128::from(secs) * 1_000);
+        let len = self.clips.len();
+        while self.clips.len() > self.capacity {
+            self.clips.pop_front();
+        }
+        self.evict_expired();
+        self.config = config.clone();
+
+        // otherwise evicted clips would come back after restart
+        if self.clips.len() < len
+            && let Some(disk) = self.disk.as_mut()
+            && let Err(err) = disk.rewrite(self.clips.iter())
+        {
+            log::error!("[store] failed to compact history: {err:?}");
+        }
     }
 
     #[must_use]
     pub(crate) fn add(&mut self, clip: &Clip) -> bool {
-        let do_update = self.clip.is_none()
-            || self
-                .clip
-                .as_ref()
-                .is_some_and(|current| clip.newer_than(current));
+        let do_update = self
+            .clips
+            .back()
+            .is_none_or(|current| clip.newer_than(current));
 
         if do_update {
-            self.clip = Some(clip.clone());
+            self.insert(clip.clone());
+            self.persist(clip);
         }
 
         do_update
     }
+
+    fn insert(&mut self, clip: Clip) {
+        self.clips.retain(|existing| !existing.same_content(&clip));
+        self.clips.push_back(clip);
+        while self.clips.len() > self.capacity {
+            self.clips.pop_front();
+        }
+    }
+
+    fn evict_expired(&mut self) {
+        let Some(max_age_ms) = self.max_age_ms else {
+            return;
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_millis();
+        // the most recent clip is kept regardless of its age
+        while self.clips.len() > 1
+            && self
+                .clips
+                .front()
+                .is_some_and(|clip| now.saturating_sub(clip.timestamp) > max_age_ms)
+        {
+            self.clips.pop_front();
+        }
+    }
+
+    fn persist(&mut self, clip: &Clip) {
+        self.evict_expired();
+        let Some(disk) = self.disk.as_mut() else {
+            return;
+        };
+
+        let result = if disk.lines() >= self.capacity * 2 {
+            disk.rewrite(self.clips.iter())
+        } else {
+            disk.append(clip)
+        };
+        if let Err(err) = result {
+            log::error!("[store] failed to persist clip: {err:?}");
+        }
+    }
+
+    pub(crate) fn history(&self) -> Vec<Clip> {
+        self.clips.iter().rev().cloned().collect()
+    }
 }
@@ -1,7 +1,9 @@
-use crate::{Config, Handle, main_loop::MainLoop};
-use anyhow::{Context as _, Result};
-use tokio::sync::mpsc::unbounded_channel;
-use tokio_util::sync::CancellationToken;
+use crate::{
+    Client, Config, Handle,
+    error::{ErrorCode, clear_last_error, set_last_error},
+    handle::Worker,
+};
+use anyhow::Result;
 
 /// Main entry point to start MPClipboard
 pub struct Thread;
@@ -9,40 +11,24 @@ pub struct Thread;
 impl Thread {
     /// Starts a background thread with Tokio runtime, returns a "handle" for communication and control.
     pub fn start(config: Config) -> Result<Handle> {
-        let (ctx, crx) = unbounded_channel();
-        let (etx, erx) = unbounded_channel();
-        let token = CancellationToken::new();
-        let (pipe_reader, pipe_writer) = std::io::pipe().context("failed to create io pipe")?;
+        let (mut handle, run) = Client::run(config)?;
 
-        let handle = {
-            let token = token.clone();
-            std::thread::spawn(move || {
-                let rt = match tokio::runtime::Builder::new_current_thread()
-                    .enable_all()
-                    .build()
-                {
-                    Ok(rt) => rt,
-                    Err(err) => {
-                        log::error!("failed to start tokio: {err:?}");
-                        return;
-                    }
-                };
+        handle.worker = Worker::Thread(std::thread::spawn(move || {
+            let rt = match tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+            {
+                Ok(rt) => rt,
+                Err(err) => {
+                    log::error!("failed to start tokio: {err:?}");
+                    return;
+                }
+            };
 
-                rt.block_on(async move {
-                    MainLoop::new(crx, etx, config, token, pipe_writer)
-                        .start()
-                        .await;
-                })
-            })
-        };
+            rt.block_on(run)
+        }));
 
-        Ok(Handle {
-            ctx,
-            erx,
-            token,
-            handle,
-            pipe_reader: Some(pipe_reader),
-        })
+        Ok(handle)
     }
 }
 
@@ -53,11 +39,12 @@ impl Thread {
 /// `config` must be a valid owned pointer to Config
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn mpclipboard_thread_start(config: *mut Config) -> *mut Handle {
+    clear_last_error();
     let config = unsafe { Box::from_raw(config) };
     let handle = match Thread::start(*config) {
         Ok(handle) => handle,
         Err(err) => {
-            log::error!("{err:?}");
+            set_last_error(ErrorCode::Io, err);
             return std::ptr::null_mut();
         }
     };
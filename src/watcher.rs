@@ -0,0 +1,43 @@
+use crate::Config;
+use std::time::SystemTime;
+
+// Polls modification time of the config file, it's checked on every tick
+// of the main loop, so there's no need for a dedicated thread or inotify.
+pub(crate) struct ConfigWatcher {
+    path: String,
+    modified: Option<SystemTime>,
+}
+
+impl ConfigWatcher {
+    pub(crate) fn new(path: String) -> Self {
+        log::info!("[config] watching {path}");
+        let modified = modified(&path);
+        Self { path, modified }
+    }
+
+    // Returns a new config if the file has changed since the last check
+    pub(crate) fn poll(&mut self) -> Option<Config> {
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        // removed file is not a reason to drop the current config
+        modified?;
+
+        log::info!("[config] {} has changed, reloading", self.path);
+        match Config::read_path(&self.path) {
+            Ok(config) => Some(config),
+            Err(err) => {
+                log::error!("[config] failed to reload: {err:?}");
+                None
+            }
+        }
+    }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
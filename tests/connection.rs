@@ -0,0 +1,720 @@
+// Drives the client against servers running on loopback: the bundled `Relay`
+// for regular scenarios and a scripted mock for misbehaving servers.
+// Everything is observed the way apps do it, by polling `Handle::recv`.
+
+use futures::{SinkExt as _, StreamExt as _};
+use http::Uri;
+use mpclipboard_generic_client::{
+    Client, Config, ConnectionState, Event, Handle, Relay, RelayConfig, RoutingPolicy,
+    ServerConfig, Update,
+};
+use std::{
+    net::SocketAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    time::Instant,
+};
+use tokio_websockets::{Message, ServerBuilder, WebSocketStream};
+
+const TOKEN: &str = "test-token";
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+fn config(uri: Uri, token: &str) -> Config {
+    let mut config = Config {
+        uri,
+        token: token.to_string(),
+        name: String::from("test"),
+        ..Config::default()
+    };
+    config.reconnect.initial_delay_ms = 100;
+    config.reconnect.max_delay_ms = 500;
+    config.reconnect.jitter = 0.0;
+    config.heartbeat.tick_interval_ms = 50;
+    config
+}
+
+fn start(config: Config) -> Handle {
+    Client::spawn_on(&tokio::runtime::Handle::current(), config).expect("failed to start client")
+}
+
+async fn relay() -> Relay {
+    relay_on(SocketAddr::from(([127, 0, 0, 1], 0))).await
+}
+
+async fn relay_on(bind: SocketAddr) -> Relay {
+    Relay::start(RelayConfig {
+        bind,
+        tokens: vec![TOKEN.to_string()],
+        ..RelayConfig::default()
+    })
+    .await
+    .expect("failed to start relay")
+}
+
+// Polls the handle until `done` returns `true`, returns the last update.
+// Every change is reported by `recv` only once, so conditions that span
+// several updates must be tracked by `done` itself.
+async fn wait_for(
+    handle: &mut Handle,
+    what: &str,
+    mut done: impl FnMut(&Update) -> bool,
+) -> Update {
+    let deadline = Instant::now() + TIMEOUT;
+    while Instant::now() < deadline {
+        let update = handle.recv();
+        if done(&update) {
+            return update;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("timed out waiting for {what}");
+}
+
+async fn wait_for_state(handle: &mut Handle, state: ConnectionState) -> Update {
+    wait_for(handle, &format!("{state:?}"), |update| {
+        update.status.is_some_and(|status| status.state == state)
+    })
+    .await
+}
+
+async fn wait_for_clip(handle: &mut Handle, text: &str) {
+    wait_for(handle, text, |update| {
+        update.clip.as_ref().and_then(|clip| clip.as_text()) == Some(text)
+    })
+    .await;
+}
+
+fn now_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis()
+}
+
+fn text_clip(text: &str, timestamp: u128) -> Message {
+    Message::text(serde_json::json!({ "text": text, "timestamp": timestamp }).to_string())
+}
+
+// Single chunk that carries the whole payload, see `chunks.rs` for the layout
+fn chunk(id: u64, payload: &[u8]) -> Vec<u8> {
+    let mut chunk = b"MPCK".to_vec();
+    chunk.extend_from_slice(&id.to_be_bytes());
+    chunk.extend_from_slice(&0_u32.to_be_bytes());
+    chunk.extend_from_slice(&1_u32.to_be_bytes());
+    chunk.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+    chunk.extend_from_slice(payload);
+    chunk
+}
+
+type Conn = WebSocketStream<TcpStream>;
+
+// Accepts every client, replies to its auth request and hands the connection
+// over to `script` (along with the 0-based number of the connection).
+// Acks are negotiated, but it's up to `script` to send them.
+// Returns URI of the server and the number of accepted connections.
+async fn mock<F, Fut>(script: F) -> (Uri, Arc<AtomicUsize>)
+where
+    F: Fn(Conn, usize) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let uri = format!("ws://{}", listener.local_addr().unwrap())
+        .parse()
+        .unwrap();
+    let connections = Arc::new(AtomicUsize::new(0));
+
+    let counter = Arc::clone(&connections);
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (_, mut conn) = ServerBuilder::new().accept(stream).await.unwrap();
+            let auth = conn.next().await.unwrap().unwrap();
+            assert!(auth.as_text().unwrap().contains(TOKEN));
+            conn.send(Message::text(r#"{"success":true,"acks":true}"#.to_string()))
+                .await
+                .unwrap();
+            let n = counter.fetch_add(1, Ordering::SeqCst);
+            tokio::spawn(script(conn, n));
+        }
+    });
+
+    (uri, connections)
+}
+
+// Keeps reading, so PINGs are answered, until the client goes away
+async fn serve(mut conn: Conn) {
+    while let Some(Ok(_)) = conn.next().await {}
+}
+
+#[tokio::test]
+async fn auth_success() {
+    let relay = relay().await;
+    let mut handle = start(config(relay.uri(), TOKEN));
+
+    let update = wait_for_state(&mut handle, ConnectionState::Connected).await;
+    assert_eq!(update.connectivity, Some(true));
+    assert_eq!(relay.clients(), 1);
+
+    handle.shutdown().await.unwrap();
+    relay.stop().await;
+}
+
+#[tokio::test]
+async fn auth_failure() {
+    let relay = relay().await;
+    let mut handle = start(config(relay.uri(), "wrong-token"));
+
+    let (mut auth_failed, mut reason) = (false, None);
+    wait_for(&mut handle, "auth failure", |update| {
+        auth_failed |= update
+            .status
+            .is_some_and(|status| status.state == ConnectionState::AuthFailed);
+        reason = reason.take().or(update.auth_error.clone());
+        auth_failed && reason.is_some()
+    })
+    .await;
+    assert_eq!(reason.as_deref(), Some("invalid token"));
+    assert_eq!(relay.clients(), 0);
+
+    // nothing is retried until credentials are updated
+    handle.update_credentials(TOKEN).unwrap();
+    wait_for_state(&mut handle, ConnectionState::Connected).await;
+    assert_eq!(relay.clients(), 1);
+
+    handle.shutdown().await.unwrap();
+    relay.stop().await;
+}
+
+#[tokio::test]
+async fn network_change_reports_connection_loss() {
+    let relay = relay().await;
+    let mut handle = start(config(relay.uri(), TOKEN));
+    wait_for_state(&mut handle, ConnectionState::Connected).await;
+
+    // reconnecting on loopback is fast, so `recv` could squash the loss
+    handle.notify_network_changed().unwrap();
+    let mut states = vec![];
+    let deadline = Instant::now() + TIMEOUT;
+    while states.last() != Some(&ConnectionState::Connected) && Instant::now() < deadline {
+        for event in handle.recv_all() {
+            if let Event::StatusChanged(status) = event {
+                states.push(status.state);
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(
+        states.first(),
+        Some(&ConnectionState::Disconnected),
+        "{states:?}"
+    );
+    assert_eq!(
+        states.last(),
+        Some(&ConnectionState::Connected),
+        "{states:?}"
+    );
+
+    handle.shutdown().await.unwrap();
+    relay.stop().await;
+}
+
+#[tokio::test]
+async fn clips_are_relayed() {
+    let relay = relay().await;
+    let sender = start(config(relay.uri(), TOKEN));
+    let mut receiver = start(config(relay.uri(), TOKEN));
+    wait_for_state(&mut receiver, ConnectionState::Connected).await;
+
+    assert!(sender.send("hello").await.unwrap());
+    wait_for_clip(&mut receiver, "hello").await;
+
+    // large clips are split into chunks and compressed
+    let image = (0..200_000_u32)
+        .map(|i| (i % 251) as u8)
+        .collect::<Vec<_>>();
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    assert!(sender.send_bytes("image/png", &image).await.unwrap());
+    wait_for(&mut receiver, "image", |update| {
+        update
+            .clip
+            .as_ref()
+            .and_then(|clip| clip.flavor("image/png"))
+            .is_some_and(|flavor| flavor.data == image)
+    })
+    .await;
+
+    sender.shutdown().await.unwrap();
+    receiver.shutdown().await.unwrap();
+    relay.stop().await;
+}
+
+#[tokio::test]
+async fn passphrase_mismatch_is_reported() {
+    let relay = relay().await;
+    let mut sender_config = config(relay.uri(), TOKEN);
+    sender_config.passphrase = Some(String::from("one"));
+    let mut receiver_config = config(relay.uri(), TOKEN);
+    receiver_config.passphrase = Some(String::from("another"));
+    let sender = start(sender_config);
+    let mut receiver = start(receiver_config);
+    wait_for_state(&mut receiver, ConnectionState::Connected).await;
+
+    assert!(sender.send("secret").await.unwrap());
+    let update = wait_for(&mut receiver, "decryption failure", |update| {
+        update.decryption_failed
+    })
+    .await;
+    assert!(update.clip.is_none());
+
+    sender.shutdown().await.unwrap();
+    receiver.shutdown().await.unwrap();
+    relay.stop().await;
+}
+
+#[tokio::test]
+async fn ping_silence_triggers_reconnect() {
+    // the first connection is never read, so client PINGs get no PONGs
+    let (uri, connections) = mock(|conn, n| async move {
+        if n == 0 {
+            std::future::pending::<()>().await;
+            drop(conn);
+        } else {
+            serve(conn).await;
+        }
+    })
+    .await;
+    let mut config = config(uri, TOKEN);
+    config.heartbeat.ping_interval_secs = 1;
+    config.heartbeat.liveness_timeout_secs = 2;
+    let mut handle = start(config);
+
+    wait_for_state(&mut handle, ConnectionState::Connected).await;
+    let connected_at = Instant::now();
+    wait_for(&mut handle, "connection loss", |update| {
+        update.connectivity == Some(false)
+    })
+    .await;
+    assert!(connected_at.elapsed() >= Duration::from_millis(1500));
+
+    wait_for_state(&mut handle, ConnectionState::Connected).await;
+    assert_eq!(connections.load(Ordering::SeqCst), 2);
+
+    handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn fresh_connection_gets_full_liveness_timeout() {
+    // the first connection is dropped, so the second one is made after the initial deadline
+    let (uri, connections) = mock(|conn, n| async move {
+        if n == 0 {
+            drop(conn);
+        } else {
+            serve(conn).await;
+        }
+    })
+    .await;
+    let mut config = config(uri, TOKEN);
+    config.reconnect.initial_delay_ms = 2500;
+    config.reconnect.max_delay_ms = 2500;
+    config.heartbeat.ping_interval_secs = 0;
+    config.heartbeat.liveness_timeout_secs = 2;
+    let mut handle = start(config);
+
+    wait_for(&mut handle, "second connection", |_| {
+        connections.load(Ordering::SeqCst) == 2
+    })
+    .await;
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let states = handle
+        .recv_all()
+        .into_iter()
+        .filter_map(|event| match event {
+            Event::StatusChanged(status) => Some(status.state),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert!(
+        !states.contains(&ConnectionState::Disconnected),
+        "{states:?}"
+    );
+
+    handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn stalled_handshake_times_out() {
+    // accepts TCP connections, but never completes the WebSocket handshake
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let uri = format!("ws://{}", listener.local_addr().unwrap())
+        .parse()
+        .unwrap();
+    let connections = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&connections);
+    tokio::spawn(async move {
+        let mut streams = vec![];
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            counter.fetch_add(1, Ordering::SeqCst);
+            streams.push(stream);
+        }
+    });
+    let mut config = config(uri, TOKEN);
+    config.heartbeat.connect_timeout_secs = 1;
+    let mut handle = start(config);
+
+    let started_at = Instant::now();
+    wait_for_state(&mut handle, ConnectionState::Disconnected).await;
+    assert!(started_at.elapsed() >= Duration::from_millis(900));
+    wait_for(&mut handle, "second attempt", |_| {
+        connections.load(Ordering::SeqCst) >= 2
+    })
+    .await;
+
+    handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn liveness_timeout_ignores_disconnected_servers() {
+    let mut config = config("ws://127.0.0.1:1".parse().unwrap(), TOKEN);
+    config.reconnect.initial_delay_ms = 60_000;
+    config.reconnect.max_delay_ms = 60_000;
+    config.heartbeat.liveness_timeout_secs = 1;
+    let mut handle = start(config);
+
+    // the only Disconnected is the one of the failed attempt, the backoff is not interrupted
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+    let disconnects = handle
+        .recv_all()
+        .into_iter()
+        .filter(|event| {
+            matches!(event, Event::StatusChanged(status) if status.state == ConnectionState::Disconnected)
+        })
+        .count();
+    assert_eq!(disconnects, 1);
+
+    handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn pending_clips_are_flushed_after_reconnect() {
+    let relay = relay().await;
+    let addr = relay.local_addr();
+    // the relay doesn't store clips, so the sender must not come back before the receiver
+    let mut sender_config = config(relay.uri(), TOKEN);
+    sender_config.reconnect.initial_delay_ms = 60_000;
+    sender_config.reconnect.max_delay_ms = 60_000;
+    let mut sender = start(sender_config);
+    let mut receiver = start(config(relay.uri(), TOKEN));
+    wait_for_state(&mut sender, ConnectionState::Connected).await;
+    wait_for_state(&mut receiver, ConnectionState::Connected).await;
+
+    relay.stop().await;
+    wait_for_state(&mut sender, ConnectionState::Disconnected).await;
+
+    assert!(sender.send("sent while offline").await.unwrap());
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let update = sender.recv();
+    assert!(update.delivered.is_empty() && update.failed.is_empty());
+
+    let relay = relay_on(addr).await;
+    wait_for_state(&mut receiver, ConnectionState::Connected).await;
+    sender.reconnect_now().unwrap();
+    wait_for_clip(&mut receiver, "sent while offline").await;
+    wait_for(&mut sender, "delivery", |update| {
+        update
+            .delivered
+            .iter()
+            .any(|clip| clip.as_text() == Some("sent while offline"))
+    })
+    .await;
+
+    sender.shutdown().await.unwrap();
+    receiver.shutdown().await.unwrap();
+    relay.stop().await;
+}
+
+fn server(profile: &str, uri: Uri) -> ServerConfig {
+    ServerConfig {
+        profile: profile.to_string(),
+        uri,
+        token: TOKEN.to_string(),
+        passphrase: None,
+        send: true,
+    }
+}
+
+async fn wait_for_relay_clients(relay: &Relay, clients: usize) {
+    let deadline = Instant::now() + TIMEOUT;
+    while relay.clients() != clients {
+        assert!(
+            Instant::now() < deadline,
+            "timed out waiting for {clients} client(s)"
+        );
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[tokio::test]
+async fn failover_moves_to_the_next_server() {
+    let (primary, backup) = (relay().await, relay().await);
+    let mut sender_config = config(primary.uri(), TOKEN);
+    sender_config.servers = vec![server("backup", backup.uri())];
+    sender_config.routing = RoutingPolicy::Failover;
+    let mut sender = start(sender_config);
+    let mut primary_receiver = start(config(primary.uri(), TOKEN));
+    let mut backup_receiver = start(config(backup.uri(), TOKEN));
+    wait_for_relay_clients(&primary, 2).await;
+    wait_for_relay_clients(&backup, 2).await;
+
+    // only the first connected server gets the clip
+    assert!(sender.send("to primary").await.unwrap());
+    wait_for_clip(&mut primary_receiver, "to primary").await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(backup_receiver.recv().clip.is_none());
+
+    // overall status is the one of the most alive server
+    sender.recv_all();
+    primary.stop().await;
+    let mut events = vec![];
+    let deadline = Instant::now() + TIMEOUT;
+    let overall = loop {
+        assert!(
+            Instant::now() < deadline,
+            "timed out waiting for {events:?}"
+        );
+        events.extend(sender.recv_all());
+        let overall = events
+            .iter()
+            .skip_while(|event| {
+                !matches!(event, Event::ProfileStatusChanged(profile, status)
+                    if profile == "default" && status.state == ConnectionState::Disconnected)
+            })
+            .find_map(|event| match event {
+                Event::StatusChanged(status) => Some(status.state),
+                _ => None,
+            });
+        if let Some(overall) = overall {
+            break overall;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    };
+    assert_eq!(overall, ConnectionState::Connected);
+
+    assert!(sender.send("to backup").await.unwrap());
+    wait_for_clip(&mut backup_receiver, "to backup").await;
+
+    sender.shutdown().await.unwrap();
+    primary_receiver.shutdown().await.unwrap();
+    backup_receiver.shutdown().await.unwrap();
+    backup.stop().await;
+}
+
+#[tokio::test]
+async fn clips_are_sent_to_every_server() {
+    let (first, second) = (relay().await, relay().await);
+    let mut sender_config = config(first.uri(), TOKEN);
+    sender_config.servers = vec![server("second", second.uri())];
+    sender_config.routing = RoutingPolicy::All;
+    let mut sender = start(sender_config);
+    let mut first_receiver = start(config(first.uri(), TOKEN));
+    let mut second_receiver = start(config(second.uri(), TOKEN));
+    wait_for_relay_clients(&first, 2).await;
+    wait_for_relay_clients(&second, 2).await;
+
+    assert!(sender.send("everywhere").await.unwrap());
+    wait_for_clip(&mut first_receiver, "everywhere").await;
+    wait_for_clip(&mut second_receiver, "everywhere").await;
+    // delivery is reported once, not per server
+    let mut delivered = 0;
+    let deadline = Instant::now() + Duration::from_millis(500);
+    while Instant::now() < deadline {
+        delivered += sender.recv().delivered.len();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(delivered, 1);
+
+    sender.shutdown().await.unwrap();
+    first_receiver.shutdown().await.unwrap();
+    second_receiver.shutdown().await.unwrap();
+    first.stop().await;
+    second.stop().await;
+}
+
+#[tokio::test]
+async fn removed_profiles_are_dropped_on_reload() {
+    let (main, extra) = (relay().await, relay().await);
+    let dir = std::env::temp_dir().join(format!("mpclipboard-{}-reload", std::process::id()));
+    let mut base = config(main.uri(), TOKEN);
+    base.routing = RoutingPolicy::All;
+    base.queue.persist = true;
+    base.queue.path = Some(dir.join("queue.jsonl"));
+    let mut with_extra = base.clone();
+    with_extra.servers = vec![
+        server("extra", extra.uri()),
+        // never connects, so its clips stay queued
+        server("offline", "ws://127.0.0.1:1".parse().unwrap()),
+    ];
+    let mut handle = start(with_extra);
+    wait_for_relay_clients(&main, 1).await;
+    wait_for_relay_clients(&extra, 1).await;
+    assert!(handle.send("queued").await.unwrap());
+    let offline_queue = dir.join("queue-offline.jsonl");
+    let content = std::fs::read_to_string(&offline_queue).unwrap();
+    assert!(content.contains("queued"), "{content}");
+
+    handle.update_config(base).unwrap();
+    wait_for_relay_clients(&extra, 0).await;
+    let (mut extra_gone, mut offline_gone) = (false, false);
+    wait_for(&mut handle, "removal of profiles", |update| {
+        let is_gone = |profile: &str| {
+            update
+                .profiles
+                .get(profile)
+                .is_some_and(|status| status.state == ConnectionState::Disconnected)
+        };
+        extra_gone |= is_gone("extra");
+        offline_gone |= is_gone("offline");
+        extra_gone && offline_gone
+    })
+    .await;
+    assert_eq!(main.clients(), 1);
+    // the queue of the removed profile is dropped as well
+    assert_eq!(std::fs::read_to_string(&offline_queue).unwrap(), "");
+
+    handle.shutdown().await.unwrap();
+    main.stop().await;
+    extra.stop().await;
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn unacknowledged_clips_are_kept_on_disk() {
+    let path = std::env::temp_dir().join(format!(
+        "mpclipboard-{}-unacked-queue.jsonl",
+        std::process::id()
+    ));
+    let queue_config = |uri| {
+        let mut config = config(uri, TOKEN);
+        config.queue.persist = true;
+        config.queue.path = Some(path.clone());
+        config
+    };
+
+    let (received_tx, mut received_rx) = tokio::sync::mpsc::unbounded_channel();
+    let (uri, _) = mock(move |mut conn, _| {
+        let received_tx = received_tx.clone();
+        async move {
+            // clips are read, but never acknowledged
+            while let Some(Ok(message)) = conn.next().await {
+                if message.is_text() {
+                    let _ = received_tx.send(());
+                }
+            }
+        }
+    })
+    .await;
+    let mut sender = start(queue_config(uri));
+    wait_for_state(&mut sender, ConnectionState::Connected).await;
+    assert!(sender.send("unacked").await.unwrap());
+    tokio::time::timeout(TIMEOUT, received_rx.recv())
+        .await
+        .expect("timed out waiting for the clip");
+    sender.shutdown().await.unwrap();
+    let content = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(content.lines().count(), 1);
+    assert!(content.contains("unacked"), "{content}");
+
+    // the clip is sent again after a restart and removed once it's acknowledged
+    let relay = relay().await;
+    let mut receiver = start(config(relay.uri(), TOKEN));
+    wait_for_state(&mut receiver, ConnectionState::Connected).await;
+    let mut sender = start(queue_config(relay.uri()));
+    wait_for_clip(&mut receiver, "unacked").await;
+    wait_for(&mut sender, "delivery", |update| {
+        !update.delivered.is_empty()
+    })
+    .await;
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+
+    sender.shutdown().await.unwrap();
+    receiver.shutdown().await.unwrap();
+    relay.stop().await;
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn duplicates_and_stale_clips_are_dropped() {
+    let timestamp = now_ms();
+    let (uri, _) = mock(move |mut conn, _| async move {
+        for message in [
+            text_clip("first", timestamp),
+            text_clip("first", timestamp),
+            text_clip("stale", timestamp - 1_000),
+            text_clip("last", timestamp + 1),
+        ] {
+            conn.send(message).await.unwrap();
+        }
+        serve(conn).await;
+    })
+    .await;
+    let mut handle = start(config(uri, TOKEN));
+
+    wait_for_clip(&mut handle, "last").await;
+    let history = handle.history().await.unwrap();
+    let texts = history
+        .iter()
+        .map(|clip| clip.as_text().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(texts, ["last", "first"]);
+
+    handle.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn malformed_frames() {
+    let (uri, connections) = mock(|mut conn, n| async move {
+        if n == 0 {
+            // broken chunk is skipped, the connection stays alive
+            conn.send(Message::binary(b"MPCK\x00\x01".to_vec()))
+                .await
+                .unwrap();
+            // so is a chunk that carries another chunk
+            let nested = chunk(1, &chunk(2, b"payload"));
+            conn.send(Message::binary(chunk(3, &nested))).await.unwrap();
+            conn.send(text_clip("after broken chunk", now_ms()))
+                .await
+                .unwrap();
+            // garbage clip means the server is broken, the client reconnects
+            conn.send(Message::text(String::from("{not json")))
+                .await
+                .unwrap();
+        }
+        serve(conn).await;
+    })
+    .await;
+    let mut handle = start(config(uri, TOKEN));
+
+    let (mut rejected, mut received, mut disconnected) = (false, false, false);
+    wait_for(
+        &mut handle,
+        "rejection, clip and connection loss",
+        |update| {
+            rejected |= update.clip_rejected;
+            received |=
+                update.clip.as_ref().and_then(|clip| clip.as_text()) == Some("after broken chunk");
+            disconnected |= update.connectivity == Some(false);
+            rejected && received && disconnected
+        },
+    )
+    .await;
+    wait_for_state(&mut handle, ConnectionState::Connected).await;
+    assert_eq!(connections.load(Ordering::SeqCst), 2);
+
+    handle.shutdown().await.unwrap();
+}
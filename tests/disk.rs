@@ -0,0 +1,74 @@
+// Files written by previous runs may be corrupted in any way,
+// only the broken lines must be lost.
+
+use mpclipboard_generic_client::{Client, Config};
+use std::path::PathBuf;
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("mpclipboard-{}-{name}", std::process::id()))
+}
+
+#[tokio::test]
+async fn history_skips_broken_lines() {
+    let path = temp_path("history.jsonl");
+    let mut content = br#"{"text":"first","timestamp":1000}"#.to_vec();
+    content.extend_from_slice(b"\n\xff\xfe not utf-8\n{\"text\":");
+    content.extend_from_slice(b"\n{\"text\":\"last\",\"timestamp\":2000}\n");
+    std::fs::write(&path, content).unwrap();
+
+    let mut config = Config {
+        uri: "ws://127.0.0.1:1".parse().unwrap(),
+        ..Config::default()
+    };
+    config.history.persist = true;
+    config.history.path = Some(path.clone());
+    let (handle, run) = Client::run(config).unwrap();
+    tokio::spawn(run);
+
+    let history = handle.history().await.unwrap();
+    let texts = history
+        .iter()
+        .map(|clip| clip.as_text().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(texts, ["last", "first"]);
+
+    // broken lines are dropped from the file, valid ones are kept
+    let content = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(content.lines().count(), 2);
+
+    handle.shutdown().await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn history_is_trimmed_on_disk_when_limits_shrink() {
+    let path = temp_path("shrinking-history.jsonl");
+    let content = (1..=3)
+        .map(|idx| format!("{{\"text\":\"clip {idx}\",\"timestamp\":{idx}000}}\n"))
+        .collect::<String>();
+    std::fs::write(&path, content).unwrap();
+
+    let mut config = Config {
+        uri: "ws://127.0.0.1:1".parse().unwrap(),
+        ..Config::default()
+    };
+    config.history.persist = true;
+    config.history.path = Some(path.clone());
+    let (handle, run) = Client::run(config.clone()).unwrap();
+    tokio::spawn(run);
+    assert_eq!(handle.history().await.unwrap().len(), 3);
+
+    config.history.size = 1;
+    handle.update_config(config).unwrap();
+    let history = handle.history().await.unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].as_text(), Some("clip 3"));
+
+    // evicted clips don't come back after restart
+    let content = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(content.lines().count(), 1);
+    assert!(content.contains("clip 3"));
+
+    handle.shutdown().await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
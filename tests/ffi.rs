@@ -0,0 +1,305 @@
+// Exercises every allocation that crosses the C ABI, every value is released
+// with its matching free function. Meant to be run under valgrind
+// (see `just valgrind-ffi`) to catch leaks and allocator mismatches.
+
+use mpclipboard_generic_client::{
+    ConfigReadOption, ErrorCode, EventKind, FlavorInput, Handle, mpclipboard_config_free,
+    mpclipboard_config_new, mpclipboard_config_read, mpclipboard_config_set_passphrase,
+    mpclipboard_event_list_free, mpclipboard_handle_history, mpclipboard_handle_poll,
+    mpclipboard_handle_poll_many, mpclipboard_handle_send, mpclipboard_handle_send_bytes,
+    mpclipboard_handle_send_bytes_with_callback, mpclipboard_handle_send_flavors,
+    mpclipboard_handle_send_flavors_with_callback, mpclipboard_handle_send_with_callback,
+    mpclipboard_handle_set_callback, mpclipboard_handle_set_supported_mimes,
+    mpclipboard_handle_stop, mpclipboard_handle_update_config, mpclipboard_history_free,
+    mpclipboard_init, mpclipboard_last_error_code, mpclipboard_last_error_message,
+    mpclipboard_output_free, mpclipboard_string_free, mpclipboard_thread_start,
+};
+use std::{
+    ffi::{CStr, c_char, c_void},
+    sync::{
+        Once,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+fn init() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| assert!(mpclipboard_init()));
+}
+
+// Nothing listens on port 1, so the client keeps reconnecting
+// and reports its status without needing a server.
+fn unreachable_config() -> *mut mpclipboard_generic_client::Config {
+    let config = mpclipboard_config_new(
+        c"ws://127.0.0.1:1".as_ptr(),
+        c"token".as_ptr(),
+        c"ffi-test".as_ptr(),
+    );
+    assert!(!config.is_null());
+    config
+}
+
+fn start() -> *mut Handle {
+    init();
+    let handle = unsafe { mpclipboard_thread_start(unreachable_config()) };
+    assert!(!handle.is_null());
+    handle
+}
+
+fn stop(handle: *mut Handle) {
+    assert!(unsafe { mpclipboard_handle_stop(handle) });
+}
+
+fn text(ptr: *const c_char) -> Option<String> {
+    if ptr.is_null() {
+        return None;
+    }
+    Some(unsafe { CStr::from_ptr(ptr) }.to_str().unwrap().to_string())
+}
+
+#[test]
+fn config() {
+    let config = unreachable_config();
+    assert!(unsafe { mpclipboard_config_set_passphrase(config, c"secret".as_ptr()) });
+    unsafe { mpclipboard_config_free(config) };
+
+    let config = mpclipboard_config_new(c"not a uri".as_ptr(), c"token".as_ptr(), c"name".as_ptr());
+    assert!(config.is_null());
+    assert_eq!(mpclipboard_last_error_code(), ErrorCode::InvalidUri);
+    let message = mpclipboard_last_error_message();
+    assert!(text(message).unwrap().contains("not a uri"));
+    unsafe { mpclipboard_string_free(message) };
+    unsafe { mpclipboard_config_free(config) };
+
+    // every fallible call resets the last error
+    let config = unreachable_config();
+    assert_eq!(mpclipboard_last_error_code(), ErrorCode::None);
+    assert!(mpclipboard_last_error_message().is_null());
+    unsafe { mpclipboard_config_free(config) };
+
+    // either missing or valid, both have to be released
+    let config = mpclipboard_config_read(ConfigReadOption::FromLocalFile);
+    unsafe { mpclipboard_config_free(config) };
+}
+
+#[test]
+fn update_config() {
+    let handle = start();
+    assert!(unsafe { mpclipboard_handle_update_config(handle, unreachable_config()) });
+    stop(handle);
+}
+
+#[test]
+fn poll() {
+    let handle = start();
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut has_status = false;
+    while !has_status && Instant::now() < deadline {
+        let output = unsafe { mpclipboard_handle_poll(handle) };
+        has_status = output.has_status;
+        unsafe { mpclipboard_output_free(output) };
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert!(has_status);
+
+    // the caller can take over a string and release it on its own
+    let mut output = unsafe { mpclipboard_handle_poll(handle) };
+    unsafe { mpclipboard_string_free(output.text) };
+    output.text = std::ptr::null_mut();
+    unsafe { mpclipboard_output_free(output) };
+
+    stop(handle);
+}
+
+#[test]
+fn poll_many() {
+    let handle = start();
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut statuses = 0;
+    while statuses < 2 && Instant::now() < deadline {
+        let events = unsafe { mpclipboard_handle_poll_many(handle) };
+        if !events.items.is_null() {
+            let items = unsafe { std::slice::from_raw_parts(events.items, events.len) };
+            statuses += items
+                .iter()
+                .filter(|item| item.kind == EventKind::StatusChanged)
+                .count();
+        }
+        unsafe { mpclipboard_event_list_free(events) };
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert!(statuses >= 2);
+
+    stop(handle);
+}
+
+extern "C" fn count_events(user_data: *mut c_void) {
+    let events = unsafe { &*user_data.cast::<AtomicUsize>() };
+    events.fetch_add(1, Ordering::SeqCst);
+}
+
+#[test]
+fn event_callback() {
+    let handle = start();
+    let events = AtomicUsize::new(0);
+    let user_data = (&raw const events).cast_mut().cast::<c_void>();
+    assert!(unsafe { mpclipboard_handle_set_callback(handle, Some(count_events), user_data) });
+
+    // connection attempts to the unreachable server report status changes
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while events.load(Ordering::SeqCst) == 0 && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert!(events.load(Ordering::SeqCst) > 0);
+
+    // the event is already there when the callback fires
+    let output = unsafe { mpclipboard_handle_poll(handle) };
+    assert!(output.has_status);
+    unsafe { mpclipboard_output_free(output) };
+
+    // `events` must outlive the callback
+    assert!(unsafe { mpclipboard_handle_set_callback(handle, None, std::ptr::null_mut()) });
+    stop(handle);
+}
+
+#[test]
+fn history() {
+    let handle = start();
+
+    let history = unsafe { mpclipboard_handle_history(handle) };
+    assert_eq!(history.len, 0);
+    unsafe { mpclipboard_history_free(history) };
+
+    // clips made within the same millisecond are treated as duplicates
+    assert!(unsafe { mpclipboard_handle_send(handle, c"hello".as_ptr()) });
+    std::thread::sleep(Duration::from_millis(5));
+    let png = [1_u8, 2, 3, 4];
+    assert!(unsafe {
+        mpclipboard_handle_send_bytes(handle, c"image/png".as_ptr(), png.as_ptr(), png.len())
+    });
+    std::thread::sleep(Duration::from_millis(5));
+    let html = b"<b>rich</b>";
+    let flavors = [
+        FlavorInput {
+            mime: c"text/html".as_ptr(),
+            data: html.as_ptr(),
+            len: html.len(),
+        },
+        FlavorInput {
+            mime: c"text/plain".as_ptr(),
+            data: b"rich".as_ptr(),
+            len: 4,
+        },
+    ];
+    assert!(unsafe { mpclipboard_handle_send_flavors(handle, flavors.as_ptr(), flavors.len()) });
+
+    let mimes = [c"text/plain".as_ptr(), c"image/png".as_ptr()];
+    assert!(unsafe { mpclipboard_handle_set_supported_mimes(handle, mimes.as_ptr(), mimes.len()) });
+
+    let history = unsafe { mpclipboard_handle_history(handle) };
+    let items = unsafe { std::slice::from_raw_parts(history.items, history.len) };
+    assert_eq!(items.len(), 3);
+    assert_eq!(text(items[0].text).as_deref(), Some("rich"));
+    assert_eq!(text(items[1].mime).as_deref(), Some("image/png"));
+    assert_eq!(
+        unsafe { std::slice::from_raw_parts(items[1].data, items[1].len) },
+        png
+    );
+    assert_eq!(text(items[2].text).as_deref(), Some("hello"));
+    unsafe { mpclipboard_history_free(history) };
+
+    stop(handle);
+}
+
+extern "C" fn count_failures(user_data: *mut c_void, delivered: bool) {
+    assert!(!delivered);
+    let failures = unsafe { &*user_data.cast::<AtomicUsize>() };
+    failures.fetch_add(1, Ordering::SeqCst);
+}
+
+#[test]
+fn pending_callbacks_fail_on_stop() {
+    let handle = start();
+    let failures = AtomicUsize::new(0);
+    let user_data = (&raw const failures).cast_mut().cast::<c_void>();
+
+    assert!(unsafe {
+        mpclipboard_handle_send_with_callback(handle, c"hello".as_ptr(), count_failures, user_data)
+    });
+    std::thread::sleep(Duration::from_millis(5));
+    let png = [1_u8, 2, 3, 4];
+    assert!(unsafe {
+        mpclipboard_handle_send_bytes_with_callback(
+            handle,
+            c"image/png".as_ptr(),
+            png.as_ptr(),
+            png.len(),
+            count_failures,
+            user_data,
+        )
+    });
+    std::thread::sleep(Duration::from_millis(5));
+    let flavors = [FlavorInput {
+        mime: c"text/plain".as_ptr(),
+        data: b"rich".as_ptr(),
+        len: 4,
+    }];
+    assert!(unsafe {
+        mpclipboard_handle_send_flavors_with_callback(
+            handle,
+            flavors.as_ptr(),
+            flavors.len(),
+            count_failures,
+            user_data,
+        )
+    });
+
+    // the server is unreachable, so clips are still queued when the client stops
+    stop(handle);
+    assert_eq!(failures.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn oversized_clip_is_invalid_input() {
+    init();
+    let config = unreachable_config();
+    unsafe { (*config).transfer.max_clip_size = 4 };
+    let handle = unsafe { mpclipboard_thread_start(config) };
+    assert!(!handle.is_null());
+
+    let data = [0_u8; 8];
+    assert!(!unsafe {
+        mpclipboard_handle_send_bytes(handle, c"image/png".as_ptr(), data.as_ptr(), data.len())
+    });
+    assert_eq!(mpclipboard_last_error_code(), ErrorCode::ClipTooLarge);
+
+    // small enough clips still go through
+    assert!(unsafe { mpclipboard_handle_send(handle, c"ok".as_ptr()) });
+    assert_eq!(mpclipboard_last_error_code(), ErrorCode::None);
+
+    stop(handle);
+}
+
+#[test]
+fn null_mime_is_invalid_argument() {
+    let handle = start();
+
+    let data = [1_u8, 2, 3];
+    assert!(!unsafe {
+        mpclipboard_handle_send_bytes(handle, std::ptr::null(), data.as_ptr(), data.len())
+    });
+    assert_eq!(mpclipboard_last_error_code(), ErrorCode::InvalidArgument);
+
+    let flavors = [FlavorInput {
+        mime: std::ptr::null(),
+        data: data.as_ptr(),
+        len: data.len(),
+    }];
+    assert!(!unsafe { mpclipboard_handle_send_flavors(handle, flavors.as_ptr(), flavors.len()) });
+    assert_eq!(mpclipboard_last_error_code(), ErrorCode::InvalidArgument);
+
+    stop(handle);
+}
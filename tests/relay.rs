@@ -0,0 +1,108 @@
+// Backpressure and liveness checks of the bundled `Relay`,
+// clients are raw WebSocket connections that misbehave on purpose.
+
+use futures::{SinkExt as _, StreamExt as _};
+use mpclipboard_generic_client::{Client, Config, Relay, RelayConfig};
+use std::{net::SocketAddr, time::Duration};
+use tokio::{net::TcpStream, time::Instant};
+use tokio_websockets::{ClientBuilder, MaybeTlsStream, Message, WebSocketStream};
+
+const TOKEN: &str = "test-token";
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+type Conn = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn relay(config: RelayConfig) -> Relay {
+    Relay::start(RelayConfig {
+        bind: SocketAddr::from(([127, 0, 0, 1], 0)),
+        tokens: vec![TOKEN.to_string()],
+        ..config
+    })
+    .await
+    .expect("failed to start relay")
+}
+
+async fn connect(relay: &Relay, name: &str) -> Conn {
+    let (mut conn, _) = ClientBuilder::from_uri(relay.uri())
+        .connect()
+        .await
+        .expect("failed to connect");
+    let auth = serde_json::json!({ "name": name, "token": TOKEN });
+    conn.send(Message::text(auth.to_string())).await.unwrap();
+    let reply = conn.next().await.unwrap().unwrap();
+    assert!(reply.as_text().unwrap().contains(r#""success":true"#));
+    conn
+}
+
+async fn wait_for_clients(relay: &Relay, clients: usize) {
+    let deadline = Instant::now() + TIMEOUT;
+    while relay.clients() != clients {
+        assert!(
+            Instant::now() < deadline,
+            "timed out waiting for {clients} client(s)"
+        );
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[tokio::test]
+async fn lagging_clients_are_disconnected() {
+    let relay = relay(RelayConfig {
+        client_queue_size: 1,
+        ping_interval_secs: 0,
+        ..RelayConfig::default()
+    })
+    .await;
+    // never reads, so relayed clips pile up in its queue
+    let _slow = connect(&relay, "slow").await;
+    let mut fast = connect(&relay, "fast").await;
+    wait_for_clients(&relay, 2).await;
+
+    let text = "x".repeat(64 * 1024);
+    for timestamp in 1..=512 {
+        let clip = serde_json::json!({ "text": text, "timestamp": timestamp });
+        fast.send(Message::text(clip.to_string())).await.unwrap();
+        if relay.clients() == 1 {
+            break;
+        }
+    }
+    wait_for_clients(&relay, 1).await;
+
+    relay.stop().await;
+}
+
+#[tokio::test]
+async fn silent_clients_are_disconnected() {
+    let relay = relay(RelayConfig {
+        ping_interval_secs: 1,
+        ..RelayConfig::default()
+    })
+    .await;
+
+    // the client answers PINGs, so it stays connected
+    let mut config = Config {
+        uri: relay.uri(),
+        token: TOKEN.to_string(),
+        name: String::from("test"),
+        ..Config::default()
+    };
+    config.heartbeat.tick_interval_ms = 50;
+    let handle = Client::spawn_on(&tokio::runtime::Handle::current(), config).unwrap();
+    wait_for_clients(&relay, 1).await;
+
+    let mut silent = connect(&relay, "silent").await;
+    let ping = tokio::time::timeout(TIMEOUT, silent.next())
+        .await
+        .expect("no PING from the server")
+        .unwrap()
+        .unwrap();
+    assert!(ping.is_ping());
+    wait_for_clients(&relay, 2).await;
+
+    // stops reading, so PINGs are never answered
+    tokio::time::sleep(Duration::from_secs(4)).await;
+    assert_eq!(relay.clients(), 1);
+
+    handle.shutdown().await.unwrap();
+    relay.stop().await;
+}
@@ -0,0 +1,109 @@
+// Hot reload of the config file watched with `Handle::watch_config`.
+// The file is looked up in $HOME, so this test has a process of its own.
+
+use mpclipboard_generic_client::{
+    Client, Config, ConfigReadOption, ConnectionState, Handle, Relay, RelayConfig,
+};
+use std::{
+    net::SocketAddr,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+use tokio::time::Instant;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+async fn relay(token: &str) -> Relay {
+    Relay::start(RelayConfig {
+        bind: SocketAddr::from(([127, 0, 0, 1], 0)),
+        tokens: vec![token.to_string()],
+        ..RelayConfig::default()
+    })
+    .await
+    .expect("failed to start relay")
+}
+
+// Every write gets a later `generation`, so its modification time is different
+// even on file systems with a coarse timestamp resolution
+fn write_config(path: &Path, generation: u64, relay: &Relay, token: &str, history_size: usize) {
+    let mut config = Config {
+        uri: relay.uri(),
+        token: token.to_string(),
+        name: String::from("test"),
+        ..Config::default()
+    };
+    config.history.size = history_size;
+    config.reconnect.initial_delay_ms = 100;
+    config.reconnect.max_delay_ms = 500;
+    config.heartbeat.tick_interval_ms = 50;
+    std::fs::write(path, toml::to_string(&config).unwrap()).unwrap();
+    let modified = SystemTime::now() + Duration::from_secs(generation);
+    std::fs::File::options()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .set_modified(modified)
+        .unwrap();
+}
+
+async fn wait_until(what: &str, mut done: impl FnMut() -> bool) {
+    let deadline = Instant::now() + TIMEOUT;
+    while !done() {
+        assert!(Instant::now() < deadline, "timed out waiting for {what}");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+async fn wait_for_connected(handle: &mut Handle) {
+    wait_until("connection", || {
+        handle
+            .recv()
+            .status
+            .is_some_and(|status| status.state == ConnectionState::Connected)
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn config_changes_are_applied_without_restart() {
+    let home = std::env::temp_dir().join(format!("mpclipboard-{}-home", std::process::id()));
+    let dir = home.join(".config/mpclipboard");
+    std::fs::create_dir_all(&dir).unwrap();
+    // nothing else runs in this process yet
+    unsafe { std::env::set_var("HOME", &home) };
+    let path = dir.join("config.toml");
+
+    let (first, second) = (relay("first-token").await, relay("second-token").await);
+    write_config(&path, 0, &first, "first-token", 5);
+    let config = Config::read(ConfigReadOption::FromXdgConfigDir).unwrap();
+    let mut handle = Client::spawn_on(&tokio::runtime::Handle::current(), config).unwrap();
+    handle
+        .watch_config(ConfigReadOption::FromXdgConfigDir)
+        .unwrap();
+    wait_for_connected(&mut handle).await;
+    assert_eq!(first.clients(), 1);
+
+    for text in ["one", "two", "three"] {
+        assert!(handle.send(text).await.unwrap());
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    assert_eq!(handle.history().await.unwrap().len(), 3);
+
+    // the second relay accepts only the new token
+    write_config(&path, 1, &second, "second-token", 2);
+    wait_until("move to the second relay", || {
+        first.clients() == 0 && second.clients() == 1
+    })
+    .await;
+    let history = handle.history().await.unwrap();
+    let texts = history
+        .iter()
+        .map(|clip| clip.as_text().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(texts, ["three", "two"]);
+
+    handle.shutdown().await.unwrap();
+    first.stop().await;
+    second.stop().await;
+    std::fs::remove_dir_all(&home).unwrap();
+}